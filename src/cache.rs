@@ -0,0 +1,410 @@
+use crate::class::*;
+use crate::get_classes::{ClassPage, ParseReport, SCRAPER_VERSION};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Bumped whenever the cached form of responses or classes changes,
+/// so that caches written by older versions are cleared.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Describes what a year's cache holds, stored as its manifest.json.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    schema_version: u32,
+    year: String,
+    scraper_version: u32,
+    /// The number of classes written by the last complete parse,
+    /// or None while the cached classes are incomplete.
+    classes: Option<usize>,
+}
+
+/// The cached responses and classes of one academic year,
+/// kept under ./cache/<year> next to its search index.
+pub struct Cache {
+    path: String,
+    manifest: Manifest,
+}
+
+impl Cache {
+    /// Opens the cache for an academic year, creating it if absent.
+    /// Entries written with another schema or for another year are
+    /// removed, as are classes parsed by another version of the scraper.
+    /// Caches written before there was a manifest keep their responses.
+    pub fn open(year: &str) -> io::Result<Cache> {
+        Cache::open_in("./cache", year)
+    }
+    /// Opens the cache for an academic year under another directory than ./cache.
+    fn open_in(root: &str, year: &str) -> io::Result<Cache> {
+        let path = format!("{root}/{year}");
+        adopt_unsorted_responses(root, year, &path)?;
+        // None if there is no manifest, and Some(None) if it is unreadable.
+        let manifest = match std::fs::read(format!("{path}/manifest.json")) {
+            Ok(bytes) => Some(serde_json::from_slice::<Manifest>(&bytes).ok()),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => None,
+            Err(why) => return Err(why),
+        };
+        let mut cache = Cache {
+            path,
+            manifest: Manifest {
+                schema_version: SCHEMA_VERSION,
+                year: year.to_owned(),
+                scraper_version: SCRAPER_VERSION,
+                classes: None,
+            },
+        };
+        match manifest {
+            Some(Some(manifest))
+                if manifest.schema_version == SCHEMA_VERSION && manifest.year == year =>
+            {
+                if manifest.scraper_version == SCRAPER_VERSION {
+                    cache.manifest.classes = manifest.classes;
+                } else {
+                    println!(
+                        "Removing classes parsed by an older scraper from {}...",
+                        cache.path
+                    );
+                    remove_dir(&cache.classes_path())?;
+                }
+            }
+            // Responses are still read the same way as before there was a
+            // manifest, but the scraper that parsed the classes is unknown.
+            None if Path::new(&cache.responses_path()).exists() => {
+                println!("Adding a manifest to the cache in {}...", cache.path);
+                remove_dir(&cache.classes_path())?;
+            }
+            _ => {
+                println!(
+                    "Clearing the missing or outdated cache in {}...",
+                    cache.path
+                );
+                remove_dir(&cache.responses_path())?;
+                remove_dir(&cache.classes_path())?;
+            }
+        }
+        for path in [cache.responses_path(), cache.classes_path()] {
+            std::fs::create_dir_all(path)?;
+        }
+        cache.write_manifest()?;
+        Ok(cache)
+    }
+    fn responses_path(&self) -> String {
+        format!("{}/responses", self.path)
+    }
+    fn classes_path(&self) -> String {
+        format!("{}/classes", self.path)
+    }
+    /// Returns every readable cached response.
+    pub fn responses(&self) -> io::Result<Vec<ClassPage>> {
+        read_entries(&self.responses_path())
+    }
+    /// Returns every readable cached class, even if the last parse
+    /// didn't finish writing them all.
+    pub fn classes(&self) -> io::Result<Vec<Class>> {
+        read_entries(&self.classes_path())
+    }
+    /// Returns the cached classes if the last parse finished writing
+    /// them and none of them were lost since.
+    pub fn complete_classes(&self) -> io::Result<Option<Vec<Class>>> {
+        let Some(count) = self.manifest.classes else {
+            return Ok(None);
+        };
+        let classes = self.classes()?;
+        Ok((classes.len() == count).then_some(classes))
+    }
+    pub fn write_response(&self, page: &ClassPage) -> io::Result<()> {
+        let path = format!("{}/{}", self.responses_path(), response_name(&page.link));
+        write_atomic(Path::new(&path), &serde_json::to_vec_pretty(page)?)
+    }
+    pub fn remove_response(&self, link: &str) -> io::Result<()> {
+        match std::fs::remove_file(format!("{}/{}", self.responses_path(), response_name(link))) {
+            Err(why) if why.kind() != io::ErrorKind::NotFound => Err(why),
+            _ => Ok(()),
+        }
+    }
+    /// Replaces every cached response with the given ones.
+    pub fn replace_responses(&self, pages: &[ClassPage]) -> io::Result<()> {
+        let entries = pages.iter().map(|page| (response_name(&page.link), page));
+        replace_entries(&self.responses_path(), entries)?;
+        Ok(())
+    }
+    /// Replaces every cached class with the given ones, only marking
+    /// the classes complete once all of them are written.
+    pub fn replace_classes(&mut self, classes: &[Class]) -> io::Result<()> {
+        self.manifest.classes = None;
+        self.write_manifest()?;
+        let entries = classes.iter().map(|class| (class_name(class), class));
+        let written = replace_entries(&self.classes_path(), entries)?;
+        self.manifest.classes = Some(written);
+        self.write_manifest()
+    }
+    /// Saves the report of the last parse as parse_report.json.
+    pub fn write_parse_report(&self, report: &ParseReport) -> io::Result<()> {
+        let path = format!("{}/parse_report.json", self.path);
+        write_atomic(Path::new(&path), &serde_json::to_vec_pretty(report)?)
+    }
+    fn write_manifest(&self) -> io::Result<()> {
+        let path = format!("{}/manifest.json", self.path);
+        write_atomic(
+            Path::new(&path),
+            &serde_json::to_vec_pretty(&self.manifest)?,
+        )
+    }
+}
+
+/// Returns the directory the search index of an academic year is kept
+/// in, next to its cache whether or not its source caches anything.
+pub fn index_path(year: &str) -> String {
+    format!("./cache/{year}/index")
+}
+
+/// Writes a file through a temporary file in the same directory, so that
+/// an interrupted write never leaves a partially written file behind.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{name}.tmp"));
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Reads every entry in a cache directory, removing unreadable ones
+/// so that they are queried or parsed again.
+fn read_entries<T: DeserializeOwned>(path: &str) -> io::Result<Vec<T>> {
+    let mut entries = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        // Left behind by interrupted writes.
+        if name.starts_with('.') {
+            std::fs::remove_file(&path)?;
+            continue;
+        }
+        match std::fs::read(&path).map(|bytes| serde_json::from_slice(&bytes)) {
+            Ok(Ok(entry)) => entries.push(entry),
+            _ => {
+                println!("Removing unreadable cache entry {}...", path.display());
+                std::fs::remove_file(&path)?;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Writes every named entry to a cache directory, removes the entries
+/// not among them and returns the number of entries written.
+fn replace_entries<'a, T: Serialize + 'a>(
+    path: &str,
+    entries: impl Iterator<Item = (String, &'a T)>,
+) -> io::Result<usize> {
+    let entries = entries.collect::<HashMap<_, _>>();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if !entries.contains_key(name) {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    for (name, entry) in entries.iter() {
+        let path = format!("{path}/{name}");
+        write_atomic(Path::new(&path), &serde_json::to_vec_pretty(entry)?)?;
+    }
+    Ok(entries.len())
+}
+
+/// Moves the responses of a catalog year out of ./cache/responses, where
+/// they were kept before catalogs were cached per year, unless the year
+/// already has its own responses. The classes parsed from them are
+/// removed, since they are parsed again.
+fn adopt_unsorted_responses(root: &str, year: &str, path: &str) -> io::Result<()> {
+    let unsorted = format!("{root}/responses");
+    let responses = format!("{path}/responses");
+    if Path::new(&responses).exists() {
+        return Ok(());
+    }
+    let Ok(mut entries) = std::fs::read_dir(&unsorted) else {
+        return Ok(());
+    };
+    let first = entries
+        .next()
+        .transpose()?
+        .and_then(|entry| std::fs::read(entry.path()).ok())
+        .and_then(|bytes| serde_json::from_slice::<ClassPage>(&bytes).ok());
+    if first.is_none_or(|page| !page.link.contains(&format!("/{year}/"))) {
+        return Ok(());
+    }
+    println!("Moving the {year} responses in {unsorted} to {responses}...");
+    std::fs::create_dir_all(path)?;
+    std::fs::rename(&unsorted, &responses)?;
+    remove_dir(&format!("{root}/classes"))
+}
+
+fn remove_dir(path: &str) -> io::Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(why) if why.kind() != io::ErrorKind::NotFound => Err(why),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the file name the response for a link is cached under,
+/// the link with slashes replaced.
+fn response_name(link: &str) -> String {
+    link.replace('/', "%")
+}
+
+/// Returns the file name a class is cached under, such as "cs115".
+fn class_name(class: &Class) -> String {
+    class
+        .id()
+        .chars()
+        .filter(|c| *c != ' ')
+        .map(|c| c.to_lowercase().next().unwrap())
+        .collect::<String>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_source::read_classes;
+
+    /// Returns an empty directory to open caches in, unique to the test.
+    fn cache_root(test: &str) -> String {
+        let root = std::env::temp_dir().join(format!("classy-cache-{test}-{}", std::process::id()));
+        let root = root.to_str().unwrap().to_owned();
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn page(year: &str, class: &str) -> ClassPage {
+        ClassPage {
+            link: format!("https://example.edu/en/{year}/academic-catalog/courses/cs-computer-science/100/{class}/"),
+            text: String::from("<html></html>"),
+            etag: None,
+            last_modified: None,
+            fetched_at: None,
+        }
+    }
+
+    fn fixture_classes() -> Vec<Class> {
+        read_classes(&format!(
+            "{}/tests/fixtures/catalog.json",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap()
+    }
+
+    /// Opens a cache holding one response and every fixture class.
+    fn filled_cache(root: &str, year: &str) -> Cache {
+        let mut cache = Cache::open_in(root, year).unwrap();
+        cache.write_response(&page(year, "cs-115")).unwrap();
+        cache.replace_classes(&fixture_classes()).unwrap();
+        cache
+    }
+
+    /// Rewrites one field of a cache's manifest.
+    fn edit_manifest(cache: &Cache, field: &str, value: serde_json::Value) {
+        let path = format!("{}/manifest.json", cache.path);
+        let mut manifest =
+            serde_json::from_slice::<serde_json::Value>(&std::fs::read(&path).unwrap()).unwrap();
+        manifest[field] = value;
+        std::fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn outdated_caches_are_cleared() {
+        let root = cache_root("outdated");
+        let cache = filled_cache(&root, "2023-2024");
+        edit_manifest(&cache, "schema_version", (SCHEMA_VERSION + 1).into());
+        let cache = Cache::open_in(&root, "2023-2024").unwrap();
+        assert!(cache.responses().unwrap().is_empty());
+        assert!(cache.classes().unwrap().is_empty());
+
+        let cache = filled_cache(&root, "2023-2024");
+        edit_manifest(&cache, "year", "2024-2025".into());
+        let cache = Cache::open_in(&root, "2023-2024").unwrap();
+        assert!(cache.responses().unwrap().is_empty());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn new_scrapers_keep_responses_but_parse_classes_again() {
+        let root = cache_root("scraper");
+        let cache = filled_cache(&root, "2023-2024");
+        assert_eq!(cache.complete_classes().unwrap().map(|c| c.len()), Some(3));
+        edit_manifest(&cache, "scraper_version", (SCRAPER_VERSION - 1).into());
+        let cache = Cache::open_in(&root, "2023-2024").unwrap();
+        assert_eq!(cache.responses().unwrap().len(), 1);
+        assert!(cache.classes().unwrap().is_empty());
+        assert!(cache.complete_classes().unwrap().is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn unreadable_and_interrupted_entries_are_removed() {
+        let root = cache_root("unreadable");
+        let cache = filled_cache(&root, "2023-2024");
+        let responses = cache.responses_path();
+        std::fs::write(format!("{responses}/garbled"), "{").unwrap();
+        std::fs::write(format!("{responses}/.partial.tmp"), "{").unwrap();
+        assert_eq!(cache.responses().unwrap().len(), 1);
+        assert_eq!(std::fs::read_dir(&responses).unwrap().count(), 1);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn partially_written_classes_are_incomplete() {
+        let root = cache_root("partial");
+        let cache = filled_cache(&root, "2023-2024");
+        std::fs::remove_file(format!("{}/cs115", cache.classes_path())).unwrap();
+        assert!(cache.complete_classes().unwrap().is_none());
+        assert_eq!(cache.classes().unwrap().len(), 2);
+
+        // As left by a parse interrupted before the classes were all written.
+        let cache = filled_cache(&root, "2023-2024");
+        edit_manifest(&cache, "classes", serde_json::Value::Null);
+        let cache = Cache::open_in(&root, "2023-2024").unwrap();
+        assert!(cache.complete_classes().unwrap().is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn caches_without_a_manifest_are_adopted() {
+        let root = cache_root("adopted");
+        // The layout from before catalogs were cached per year.
+        let unsorted = format!("{root}/responses");
+        std::fs::create_dir_all(&unsorted).unwrap();
+        std::fs::create_dir_all(format!("{root}/classes")).unwrap();
+        let old = page("2023-2024", "cs-115");
+        std::fs::write(
+            format!("{unsorted}/{}", response_name(&old.link)),
+            serde_json::to_vec(&old).unwrap(),
+        )
+        .unwrap();
+        // Responses of another year stay where they are.
+        let cache = Cache::open_in(&root, "2024-2025").unwrap();
+        assert!(cache.responses().unwrap().is_empty());
+        assert!(Path::new(&unsorted).exists());
+        let cache = Cache::open_in(&root, "2023-2024").unwrap();
+        assert_eq!(cache.responses().unwrap().len(), 1);
+        assert!(!Path::new(&unsorted).exists());
+        assert!(!Path::new(&format!("{root}/classes")).exists());
+
+        // A year's cache written before it had a manifest.
+        let cache = filled_cache(&root, "2022-2023");
+        std::fs::remove_file(format!("{}/manifest.json", cache.path)).unwrap();
+        let cache = Cache::open_in(&root, "2022-2023").unwrap();
+        assert_eq!(cache.responses().unwrap().len(), 1);
+        assert!(cache.classes().unwrap().is_empty());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,19 +1,31 @@
 use tantivy::{
-    collector::TopDocs, doc, query::QueryParser, schema::*, Index, IndexReader, ReloadPolicy,
+    collector::{Count, TopDocs},
+    doc,
+    query::{QueryParser, QueryParserError},
+    schema::*,
+    Index, IndexReader, ReloadPolicy, Snippet, SnippetGenerator,
 };
 
+use crate::cache::*;
 use crate::class::*;
-use crate::get_classes::*;
+use crate::diff::*;
+use crate::prerequisite::*;
 pub use crate::traits::Catalog as CatalogTrait;
-use serde_json;
-use std::collections::HashMap;
+pub use crate::traits::{CatalogSource, SourceError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use thiserror::Error;
+use tokio::sync::watch;
 
 pub struct Catalog {
+    year: String,
     classes: Vec<Class>,
     departments: HashMap<String, String>,
     schema: Schema,
     reader: IndexReader,
     query_parser: QueryParser,
+    prerequisites: PrerequisiteGraph,
     //classes_by_id: HashMap<String, &'a Class>,
     //classes_by_department: HashMap<String, Vec<&'a Class>>,
 }
@@ -21,12 +33,9 @@ pub struct Catalog {
 impl CatalogTrait<Class> for Catalog {
     fn query_by_id(&self, id: &str) -> Option<&Class> {
         let quarry = clean(id);
-        for class in self.classes.iter() {
-            if quarry == clean(&class.id()) {
-                return Some(class);
-            }
-        }
-        None
+        self.classes
+            .iter()
+            .find(|class| quarry == clean(&class.id()))
     }
     fn query_by_department(&self, department: &str) -> Vec<&Class> {
         let quarry = clean(department);
@@ -40,161 +49,78 @@ impl CatalogTrait<Class> for Catalog {
     }
 }
 impl Catalog {
-    /// Returns a final populated catalog that should not be changed.
-    pub async fn new_filled<'a>() -> Result<Catalog, std::io::Error> {
-        println!("Checking cache paths and creating if absent...");
-        for path in ["./cache/responses", "./cache/classes"] {
-            tokio::fs::create_dir_all(path).await?;
-        }
-
-        println!("Listing entries in ./cache/responses...");
-        let cached_response_names = std::fs::read_dir("./cache/responses")
-            .unwrap()
-            .map(|d| d.unwrap().file_name().to_str().unwrap().to_owned())
-            .collect::<Vec<_>>();
-
-        println!("Listing entries in ./cache/classes...");
-        let cached_class_names = std::fs::read_dir("./cache/classes")
-            .unwrap()
-            .map(|d| d.unwrap().file_name().to_str().unwrap().to_owned())
-            .collect::<Vec<_>>();
-
-        let mut classes = Vec::with_capacity(cached_class_names.len());
-        if cached_class_names.len() >= cached_response_names.len() && cached_class_names.len() != 0
-        {
-            println!(
-                "Loading {} cached classes from ./cache/classes...",
-                cached_class_names.len()
-            );
-            classes.extend(cached_class_names.iter().map(|name| {
-                let file = std::fs::File::open(format!("./cache/classes/{name}")).unwrap();
-                let reader = std::io::BufReader::new(file);
-                serde_json::from_reader(reader).unwrap()
-            }));
-        } else {
-            println!(
-                "Loading {} cached responses from ./cache/responses...",
-                cached_response_names.len()
-            );
-            let mut responses = cached_response_names
-                .iter()
-                .map(|name| {
-                    let file = std::fs::File::open(format!("./cache/responses/{name}")).unwrap();
-                    let reader = std::io::BufReader::new(file);
-                    serde_json::from_reader(reader).unwrap()
-                })
-                .collect::<Vec<_>>();
-
-            println!("Checking for missing links in cached responses...");
-            'outer: loop {
-                let query = query_classes(&responses).await;
-                for response in query {
-                    let response = match response {
-                        Ok(response) => response,
-                        Err(_) => continue 'outer,
-                    };
-                    if responses
-                        .iter()
-                        .filter(|r| (**r).link == response.link)
-                        .count()
-                        == 0
-                    {
-                        responses.push(response);
-                    }
-                }
-                break;
-            }
-
-            // println!(
-            //     "Writing {} new responses to ./cache/responses...",
-            //     responses.len() - cached_response_names.len()
-            // );
-            // for response in responses.iter() {
-            //     let sanitized_link = response.link.replace("/", "%");
-            //     if !cached_response_names.contains(&sanitized_link) {
-            //         std::fs::write(
-            //             format!("./cache/responses/{sanitized_link}"),
-            //             serde_json::to_string_pretty(&response).unwrap(),
-            //         )
-            //         .unwrap();
-            //     };
-            // }
-
-            println!("Parsing responses into Class objects...");
-            classes.extend(responses.into_iter().filter_map(|r| parse_class(r)));
-            println!("Parsed {} classes.", classes.len());
-
-            println!(
-                "Checking against {} cached classes...",
-                cached_class_names.len()
-            );
-            for class in classes.iter() {
-                let short_id = class
-                    .id()
-                    .chars()
-                    .filter(|c| *c != ' ')
-                    .map(|c| c.to_lowercase().next().unwrap())
-                    .collect::<String>();
-                if !cached_class_names.contains(&short_id) {
-                    std::fs::write(
-                        format!("./cache/classes/{short_id}"),
-                        serde_json::to_string_pretty(class).unwrap(),
-                    )
-                    .expect(&format!("{:#?}", class));
-                }
-            }
-            println!("Wrote new classes to ./cache/classes.");
+    /// Returns a populated catalog for the given academic year
+    /// (e.g. "2023-2024") from a source of classes.
+    /// Catalogs are never changed; a refresh builds a new one instead.
+    pub async fn new_filled(
+        source: &(impl CatalogSource + ?Sized),
+        year: &str,
+    ) -> Result<Catalog, SourceError> {
+        let classes = source.load(year).await?;
+        Ok(Catalog::from_classes(year, classes)?)
+    }
+    /// Returns a catalog built from the latest classes of a source for the
+    /// given academic year. Progress lines are also sent to `progress` if given.
+    pub async fn refreshed(
+        source: &(impl CatalogSource + ?Sized),
+        year: &str,
+        progress: Option<&watch::Sender<String>>,
+    ) -> Result<Catalog, SourceError> {
+        let classes = source.refresh(year, progress).await?;
+        if let Some(progress) = progress {
+            progress.send_replace(format!("Indexing {} classes...", classes.len()));
         }
 
+        // Indexing is slow enough that it shouldn't hold up the runtime.
+        let year = year.to_owned();
+        let catalog = tokio::task::spawn_blocking(move || Catalog::from_classes(&year, classes))
+            .await
+            .map_err(std::io::Error::other)??;
+        Ok(catalog)
+    }
+    fn from_classes(year: &str, classes: Vec<Class>) -> Result<Catalog, std::io::Error> {
         println!("Parsing departments from classes...");
         let mut departments = HashMap::new();
         for class in classes.iter() {
-            if !departments.contains_key(&class.department()) {
-                departments.insert(class.department(), class.department_name());
-            }
+            departments
+                .entry(class.department())
+                .or_insert_with(|| class.department_name());
         }
         println!("Parsed {} departments.", departments.len());
 
-        let mut schema_builder = Schema::builder();
-        let id = schema_builder.add_text_field("id", STORED);
-        let title = schema_builder.add_text_field(
-            "title",
-            TextOptions::default()
-                .set_indexing_options(TextFieldIndexing::default().set_tokenizer("en_stem")),
-        );
-        let body = schema_builder.add_text_field(
-            "body",
-            TextOptions::default()
-                .set_indexing_options(TextFieldIndexing::default().set_tokenizer("en_stem")),
-        );
-        let schema = schema_builder.build();
-        let index = Index::create_in_ram(schema.clone());
-        let mut index_writer = index.writer(100_000_000).unwrap();
-        for class in classes.iter() {
-            index_writer
-                .add_document(doc!(
-                    id => class.id(),
-                    title => class.title(),
-                    body => class.description(),
-                ))
-                .unwrap();
-        }
-        index_writer.commit().unwrap();
+        let schema = index_schema();
+        let index =
+            build_index(&index_path(year), &schema, &classes).map_err(std::io::Error::other)?;
         let reader = index
             .reader_builder()
             .reload_policy(ReloadPolicy::Manual)
             .try_into()
             .unwrap();
+        let title = schema.get_field("title").unwrap();
+        let body = schema.get_field("body").unwrap();
         let query_parser = QueryParser::for_index(&index, vec![title, body]);
 
+        println!("Building prerequisite graph...");
+        let prerequisites = PrerequisiteGraph::new(
+            classes
+                .iter()
+                .map(|class| (class.id(), class.prerequisite_tree())),
+        );
+
         Ok(Catalog {
+            year: year.to_owned(),
             classes,
             departments,
             schema,
             reader,
             query_parser,
+            prerequisites,
         })
     }
+    /// Returns the academic year of the catalog, such as "2023-2024".
+    pub fn year(&self) -> &str {
+        &self.year
+    }
     pub fn departments(&self) -> Vec<(String, String)> {
         let mut pairs = self
             .departments
@@ -204,28 +130,302 @@ impl Catalog {
         pairs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
         pairs
     }
-    pub fn search(&self, query: &str, number_results: usize) -> Vec<&Class> {
-        let mut classes = Vec::new();
+    /// Returns every class in a department, optionally limited to one level,
+    /// in the given order.
+    pub fn browse_department(
+        &self,
+        department: &str,
+        sort: ClassSort,
+        level: Option<Level>,
+    ) -> Vec<&Class> {
+        let mut classes = self
+            .query_by_department(department)
+            .into_iter()
+            .filter(|class| level.is_none_or(|level| class.level() == level))
+            .collect::<Vec<_>>();
+        let number = |class: &Class| class.discriminator().parse::<u32>().unwrap_or(u32::MAX);
+        match sort {
+            ClassSort::Number => classes.sort_by_key(|class| (number(class), class.id())),
+            ClassSort::Credits => classes.sort_by(|a, b| {
+                let credits = |class: &Class| class.credit_range().map_or(0.0, |(_, high)| high);
+                credits(a)
+                    .total_cmp(&credits(b))
+                    .then_with(|| number(a).cmp(&number(b)))
+            }),
+        }
+        classes
+    }
+    /// Returns up to `limit` classes with IDs close to the given ID, closest
+    /// first, for suggesting what was meant when `query_by_id` finds nothing.
+    ///
+    /// The department code and number are compared separately, so "CS 1150"
+    /// and "SC 115" both suggest CS 115. A wrong department counts double,
+    /// since "CS 115" is a likelier typo of "CS 1150" than "CE 1150" is.
+    pub fn suggest(&self, id: &str, limit: usize) -> Vec<&Class> {
+        let quarry = clean(id);
+        if quarry.is_empty() {
+            return vec![];
+        }
+        let (department, number) = split_id(&quarry);
+        // Anything further away than this is more of a guess than a typo.
+        let max_score = 2 + quarry.len() / 3;
+        let mut scored = self
+            .classes
+            .iter()
+            .map(|class| {
+                let candidate = clean(&class.id());
+                let (candidate_department, candidate_number) = split_id(&candidate);
+                let score = 2 * edit_distance(department, candidate_department)
+                    + edit_distance(number, candidate_number);
+                (score, edit_distance(&quarry, &candidate), class)
+            })
+            .filter(|(score, _, _)| *score <= max_score)
+            .collect::<Vec<_>>();
+        scored.sort_unstable_by_key(|(score, distance, class)| (*score, *distance, class.id()));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, class)| class)
+            .collect()
+    }
+    /// Returns up to `limit` classes whose IDs start with the given partial ID,
+    /// ignoring case and punctuation the same way as `query_by_id`.
+    pub fn complete_id(&self, partial: &str, limit: usize) -> Vec<&Class> {
+        let quarry = clean(partial);
+        let mut matches = self
+            .classes
+            .iter()
+            .filter(|class| clean(&class.id()).starts_with(&quarry))
+            .collect::<Vec<_>>();
+        matches.sort_unstable_by_key(|class| class.id());
+        matches.truncate(limit);
+        matches
+    }
+    /// Returns up to `limit` (code, name) department pairs whose code starts
+    /// with the given text, followed by those whose name contains it.
+    pub fn complete_department(&self, partial: &str, limit: usize) -> Vec<(String, String)> {
+        let quarry = clean(partial);
+        let (mut matches, rest): (Vec<_>, Vec<_>) = self
+            .departments()
+            .into_iter()
+            .partition(|(code, _)| clean(code).starts_with(&quarry));
+        matches.extend(
+            rest.into_iter()
+                .filter(|(_, name)| clean(name).contains(&quarry)),
+        );
+        matches.truncate(limit);
+        matches
+    }
+    /// Returns the full chain of prerequisites for a class as indented lines,
+    /// or None if the class has no prerequisites.
+    pub fn prerequisite_chain(&self, id: &str, max_depth: usize) -> Option<Vec<ChainLine>> {
+        let class = self.query_by_id(id)?;
+        self.prerequisites.chain(&class.id(), max_depth)
+    }
+    /// Returns every class that the given class leads to, directly or through
+    /// other classes, paired with how many prerequisite steps away it is.
+    /// Returns None if the class is not in the catalog.
+    pub fn unlocks(&self, id: &str) -> Option<Vec<(&Class, usize)>> {
+        let class = self.query_by_id(id)?;
+        Some(
+            self.prerequisites
+                .unlocks(&class.id())
+                .into_iter()
+                .filter_map(|(id, distance)| Some((self.query_by_id(&id)?, distance)))
+                .collect(),
+        )
+    }
+    /// Returns up to `number_results` of the best matching classes for
+    /// a query, skipping the first `offset`, along with the total number
+    /// of matching classes.
+    ///
+    /// Plain words are searched for in titles and descriptions. Words scoped
+    /// to a field, such as `dept:cs` or `offered:fall`, filter the results
+    /// to classes matching them. The numeric `level` and `credits` fields
+    /// also take ranges such as `level:300..499` or `credits:>=3`.
+    pub fn search(
+        &self,
+        query: &str,
+        offset: usize,
+        number_results: usize,
+        mode: QueryMode,
+    ) -> Result<SearchPage<'_>, SearchError> {
+        let mut hits = Vec::new();
         let searcher = self.reader.searcher();
-        let query = match self.query_parser.parse_query(query) {
-            Ok(value) => value,
-            Err(_) => return classes,
+        let query = match mode {
+            QueryMode::Strict => self.query_parser.parse_query(&prepare_query(query))?,
+            QueryMode::Lenient => self
+                .query_parser
+                .parse_query(&escape_query(&self.query_parser, query))?,
         };
-        let top_docs = match searcher.search(&query, &TopDocs::with_limit(number_results)) {
-            Ok(value) => value,
-            Err(_) => return classes,
-        };
-        for (_score, doc_address) in top_docs {
-            let retrieved_doc = searcher.doc(doc_address).unwrap();
-            let id = retrieved_doc
+        let collector = (
+            TopDocs::with_limit(number_results).and_offset(offset),
+            Count,
+        );
+        let (top_docs, total) = searcher.search(&query, &collector)?;
+        let body = self.schema.get_field("body").unwrap();
+        let mut snippets = SnippetGenerator::create(&searcher, &*query, body).ok();
+        if let Some(snippets) = snippets.as_mut() {
+            snippets.set_max_num_chars(SNIPPET_LENGTH);
+        }
+        for (score, doc_address) in top_docs {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            // An index that drifted from the classes, such as after a crash
+            // while it was being updated, may hold classes that are gone.
+            let class = retrieved_doc
                 .get_first(self.schema.get_field("id").unwrap())
-                .unwrap()
-                .as_text()
-                .unwrap();
-            classes.push(self.query_by_id(id).unwrap());
-            // println!("{} - {}", score, id);
+                .and_then(|id| id.as_text())
+                .and_then(|id| self.query_by_id(id));
+            let Some(class) = class else {
+                continue;
+            };
+            let snippet = snippets
+                .as_ref()
+                .map(|snippets| snippets.snippet(&class.description()))
+                .filter(|snippet| !snippet.highlighted().is_empty())
+                .map(|snippet| highlight(&snippet));
+            hits.push(SearchHit {
+                class,
+                score,
+                snippet,
+            });
         }
-        classes
+        Ok(SearchPage { hits, total })
+    }
+}
+
+/// How strictly to read the query syntax of a search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryMode {
+    /// Malformed queries are an error.
+    Strict,
+    /// Malformed parts of a query are searched for as plain text.
+    Lenient,
+}
+
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error("{source}")]
+    Query {
+        #[from]
+        source: QueryParserError,
+    },
+    #[error("{source}")]
+    Search {
+        #[from]
+        source: tantivy::TantivyError,
+    },
+}
+
+/// The order to list a department's classes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassSort {
+    /// By class number, lowest first.
+    Number,
+    /// By the most credits a class can be taken for, fewest first.
+    Credits,
+}
+
+#[cfg(test)]
+impl Catalog {
+    /// Returns a catalog of the given classes searching an index in
+    /// memory, for tests that shouldn't write under ./cache.
+    pub fn in_memory(year: &str, classes: Vec<Class>) -> Catalog {
+        let schema = index_schema();
+        let index = Index::create_in_ram(schema.clone());
+        let mut writer = index.writer(15_000_000).unwrap();
+        for class in classes.iter() {
+            writer.add_document(class_document(&schema, class)).unwrap();
+        }
+        writer.commit().unwrap();
+        let title = schema.get_field("title").unwrap();
+        let body = schema.get_field("body").unwrap();
+        Catalog {
+            year: year.to_owned(),
+            departments: classes
+                .iter()
+                .map(|class| (class.department(), class.department_name()))
+                .collect(),
+            reader: index.reader().unwrap(),
+            query_parser: QueryParser::for_index(&index, vec![title, body]),
+            schema,
+            prerequisites: PrerequisiteGraph::new(
+                classes
+                    .iter()
+                    .map(|class| (class.id(), class.prerequisite_tree())),
+            ),
+            classes,
+        }
+    }
+}
+
+/// One page of search results.
+pub struct SearchPage<'a> {
+    pub hits: Vec<SearchHit<'a>>,
+    /// The number of matching classes across every page.
+    pub total: usize,
+}
+
+/// A class matching a search, best matches having the highest score.
+pub struct SearchHit<'a> {
+    pub class: &'a Class,
+    pub score: f32,
+    /// An excerpt of the description with the matching terms in bold,
+    /// or None if the class only matched on other fields.
+    pub snippet: Option<String>,
+}
+
+/// Catalogs for several academic years side by side, keyed by year.
+pub struct Catalogs {
+    years: BTreeMap<String, Catalog>,
+}
+
+impl Catalogs {
+    /// Returns populated catalogs for every given academic year from
+    /// a source, each cached under its own ./cache/<year> directory.
+    pub async fn new_filled(
+        source: &(impl CatalogSource + ?Sized),
+        years: &[String],
+    ) -> Result<Catalogs, SourceError> {
+        if years.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "at least one catalog year is required",
+            )
+            .into());
+        }
+        let mut catalogs = Catalogs {
+            years: BTreeMap::new(),
+        };
+        for year in years {
+            println!("Loading the {year} catalog...");
+            catalogs.replace(Catalog::new_filled(source, year).await?);
+        }
+        Ok(catalogs)
+    }
+    /// Returns the catalog for the latest academic year.
+    pub fn newest(&self) -> &Catalog {
+        self.years
+            .values()
+            .next_back()
+            .expect("catalogs are never empty")
+    }
+    /// Returns the catalog for the given academic year, if it was loaded.
+    pub fn year(&self, year: &str) -> Option<&Catalog> {
+        self.years.get(year.trim())
+    }
+    /// Compares the catalogs of two loaded academic years, limited to a
+    /// department if one is given. Returns None if either year is not loaded.
+    pub fn diff(&self, before: &str, after: &str, department: &str) -> Option<CatalogDiff<'_>> {
+        Some(diff(self.year(before)?, self.year(after)?, department))
+    }
+    /// Swaps in a newer catalog for its academic year.
+    pub fn replace(&mut self, catalog: Catalog) {
+        self.years.insert(catalog.year.clone(), catalog);
+    }
+    /// Returns every loaded academic year, oldest first.
+    pub fn years(&self) -> Vec<&str> {
+        self.years.keys().map(|year| year.as_str()).collect()
     }
 }
 
@@ -235,3 +435,573 @@ fn clean(s: &str) -> String {
         .filter(|c| c.is_ascii_alphanumeric())
         .collect::<String>()
 }
+
+/// Bumped whenever the schema or the indexed contents of a class change,
+/// so that indexes written by older versions are rebuilt from scratch.
+const INDEX_VERSION: u32 = 2;
+
+/// Stored next to the on-disk index to tell which classes it holds.
+#[derive(Serialize, Deserialize, Default)]
+struct IndexManifest {
+    version: u32,
+    /// A hash of each indexed class, keyed by class ID.
+    classes: BTreeMap<String, u64>,
+}
+
+impl IndexManifest {
+    /// Describes an index holding exactly the given classes.
+    fn new(classes: &[Class]) -> Self {
+        Self {
+            version: INDEX_VERSION,
+            classes: classes
+                .iter()
+                .map(|class| (class.id(), class_hash(class)))
+                .collect(),
+        }
+    }
+}
+
+fn index_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+    // Indexed as a single term so that outdated classes can be deleted.
+    schema_builder.add_text_field("id", STRING | STORED);
+    let text = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer("en_stem")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    );
+    schema_builder.add_text_field("title", text.clone());
+    schema_builder.add_text_field("body", text);
+    // Structured attributes, only searched when named in the query.
+    schema_builder.add_text_field("dept", TEXT);
+    schema_builder.add_text_field("number", TEXT);
+    schema_builder.add_u64_field("level", INDEXED | FAST);
+    // The fewest and most credits a class can be taken for, searched
+    // through `credits` filters.
+    schema_builder.add_f64_field("credits_min", INDEXED | FAST);
+    schema_builder.add_f64_field("credits_max", INDEXED | FAST);
+    schema_builder.add_text_field("offered", TEXT);
+    schema_builder.add_text_field("distribution", TEXT);
+    schema_builder.add_text_field("prereqs", TEXT);
+    schema_builder.build()
+}
+
+/// Builds the index for a catalog in a new generation directory under
+/// `root`, starting from a copy of the current generation so that only
+/// changed classes are indexed again. The new generation only becomes
+/// current once it is built, so a failed build never touches the index
+/// that a running catalog is searching. The current generation is opened
+/// in place instead if it already holds exactly these classes.
+fn build_index(root: &str, schema: &Schema, classes: &[Class]) -> tantivy::Result<Index> {
+    let current_path = format!("{root}/current");
+    let current = std::fs::read_to_string(&current_path)
+        .ok()
+        .and_then(|generation| generation.trim().parse::<u64>().ok());
+    if let Some(current) = current {
+        let path = format!("{root}/{current}");
+        if let Some((index, manifest)) = read_index(&path, schema) {
+            if manifest.classes == IndexManifest::new(classes).classes {
+                println!("Index in {path} is up to date.");
+                return Ok(index);
+            }
+        }
+    }
+    let generation = current.map_or(0, |current| current + 1);
+    let path = format!("{root}/{generation}");
+    // Left behind by a build that failed.
+    if Path::new(&path).exists() {
+        std::fs::remove_dir_all(&path)?;
+    }
+    std::fs::create_dir_all(&path)?;
+    if let Some(current) = current {
+        copy_index(&format!("{root}/{current}"), &path)?;
+    }
+    let index = open_index(&path, schema, classes)?;
+    write_atomic(Path::new(&current_path), generation.to_string().as_bytes())?;
+
+    // Older generations are no longer searched by anything, except for the
+    // one a running catalog keeps searching until this one is swapped in.
+    let keep = [
+        String::from("current"),
+        generation.to_string(),
+        current
+            .map(|current| current.to_string())
+            .unwrap_or_default(),
+    ];
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        if keep.iter().any(|name| entry.file_name() == name.as_str()) {
+            continue;
+        }
+        match entry.file_type()?.is_dir() {
+            true => std::fs::remove_dir_all(entry.path())?,
+            false => std::fs::remove_file(entry.path())?,
+        }
+    }
+    Ok(index)
+}
+
+/// Copies the files of an index, if it exists, leaving out its locks.
+fn copy_index(from: &str, to: &str) -> std::io::Result<()> {
+    let entries = match std::fs::read_dir(from) {
+        Ok(entries) => entries,
+        Err(why) if why.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(why) => return Err(why),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if entry.file_type()?.is_file() && !name.to_string_lossy().ends_with(".lock") {
+            std::fs::copy(entry.path(), Path::new(to).join(name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Opens the index stored at the path, updating only the classes that
+/// were added, changed or removed since it was written. The index is
+/// rebuilt from scratch if it is missing or was written with another schema.
+fn open_index(path: &str, schema: &Schema, classes: &[Class]) -> tantivy::Result<Index> {
+    let (index, old) = match read_index(path, schema) {
+        Some(existing) => existing,
+        None => {
+            println!("Creating a new index in {path}...");
+            if Path::new(path).exists() {
+                std::fs::remove_dir_all(path)?;
+            }
+            std::fs::create_dir_all(path)?;
+            (
+                Index::create_in_dir(path, schema.clone())?,
+                IndexManifest::default(),
+            )
+        }
+    };
+
+    let new = IndexManifest::new(classes);
+    let outdated = classes
+        .iter()
+        .filter(|class| old.classes.get(&class.id()) != new.classes.get(&class.id()))
+        .collect::<Vec<_>>();
+    let removed = old
+        .classes
+        .keys()
+        .filter(|id| !new.classes.contains_key(*id))
+        .collect::<Vec<_>>();
+    if outdated.is_empty() && removed.is_empty() {
+        println!("Index in {path} is up to date.");
+        return Ok(index);
+    }
+
+    println!(
+        "Updating {} and removing {} classes in {path}...",
+        outdated.len(),
+        removed.len()
+    );
+    let id = schema.get_field("id").unwrap();
+    let mut index_writer = index.writer(50_000_000)?;
+    for class_id in removed {
+        index_writer.delete_term(Term::from_field_text(id, class_id));
+    }
+    for class in outdated {
+        // Deleted first in case the class was indexed without being
+        // recorded in the manifest.
+        index_writer.delete_term(Term::from_field_text(id, &class.id()));
+        index_writer.add_document(class_document(schema, class))?;
+    }
+    index_writer.commit()?;
+    index_writer.wait_merging_threads()?;
+    write_atomic(
+        Path::new(&format!("{path}/classes.json")),
+        &serde_json::to_vec(&new)?,
+    )?;
+    Ok(index)
+}
+
+/// Opens the index stored at the path along with its manifest, or returns
+/// None if either is missing or was written with another schema or version.
+fn read_index(path: &str, schema: &Schema) -> Option<(Index, IndexManifest)> {
+    let index = Index::open_in_dir(path)
+        .ok()
+        .filter(|index| index.schema() == *schema)?;
+    let manifest = std::fs::read(format!("{path}/classes.json")).ok()?;
+    let manifest = serde_json::from_slice::<IndexManifest>(&manifest).ok()?;
+    (manifest.version == INDEX_VERSION).then_some((index, manifest))
+}
+
+fn class_document(schema: &Schema, class: &Class) -> Document {
+    let field = |name| schema.get_field(name).unwrap();
+    let mut document = doc!(
+        field("id") => class.id(),
+        field("title") => class.title(),
+        field("body") => class.description(),
+        field("dept") => class.department(),
+        field("number") => class.discriminator(),
+        field("prereqs") => class.prerequisites(),
+    );
+    if let Ok(discriminator) = class.discriminator().parse::<u64>() {
+        document.add_u64(field("level"), discriminator);
+    }
+    if let Some((low, high)) = class.credit_range() {
+        document.add_f64(field("credits_min"), low);
+        document.add_f64(field("credits_max"), high);
+    }
+    for semester in class.offered() {
+        document.add_text(field("offered"), semester);
+    }
+    for name in class.distributions() {
+        document.add_text(field("distribution"), name);
+    }
+    document
+}
+
+/// Hashes the cached form of a class with 64 bit FNV-1a, which unlike
+/// the standard library hasher is stable across builds.
+fn class_hash(class: &Class) -> u64 {
+    serde_json::to_vec(class)
+        .unwrap()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// The longest description excerpt shown for a search hit, in characters.
+const SNIPPET_LENGTH: usize = 135;
+
+/// Renders a snippet as markdown with its highlighted terms in bold.
+fn highlight(snippet: &Snippet) -> String {
+    let fragment = snippet.fragment();
+    let mut highlighted = String::new();
+    let mut last = 0;
+    for range in snippet.highlighted() {
+        highlighted.push_str(&fragment[last..range.start]);
+        highlighted.push_str(&format!("**{}**", &fragment[range.clone()]));
+        last = range.end;
+    }
+    highlighted.push_str(&fragment[last..]);
+    format!("... {} ...", highlighted.trim())
+}
+
+/// Fields that filter search results instead of adding to their score.
+const FILTER_FIELDS: [&str; 7] = [
+    "dept",
+    "number",
+    "level",
+    "credits",
+    "offered",
+    "distribution",
+    "prereqs",
+];
+
+/// Marks every word scoped to a filter field as required, so that
+/// `dept:cs compilers` only matches CS classes instead of any CS class
+/// or any class about compilers, and expands `low..high` ranges into
+/// the `[low TO high]` syntax tantivy understands. `credits` filters
+/// are rewritten by `credits_filter`.
+fn prepare_query(query: &str) -> String {
+    let is_bound = |bound: &str| bound.is_empty() || bound.parse::<f64>().is_ok();
+    let bound = |bound: &str| match bound {
+        "" => String::from("*"),
+        bound => bound.to_owned(),
+    };
+    query
+        .split(' ')
+        .map(|word| {
+            let Some((field, value)) = word.split_once(':') else {
+                return word.to_owned();
+            };
+            if !FILTER_FIELDS.contains(&field.to_lowercase().as_str()) {
+                return word.to_owned();
+            }
+            if field.eq_ignore_ascii_case("credits") {
+                if let Some(filter) = credits_filter(value) {
+                    return filter;
+                }
+            }
+            match value.split_once("..") {
+                Some((low, high)) if is_bound(low) && is_bound(high) => {
+                    format!("+{field}:[{} TO {}]", bound(low), bound(high))
+                }
+                _ => format!("+{word}"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Rewrites the value of a `credits` filter, such as `3`, `>=3` or
+/// `1..2`, into bounds on the fewest and most credits a class can be
+/// taken for, so that it matches every class that can be taken for some
+/// number of credits in the range. A class offered for 0-3 credits
+/// matches `credits:1..2`. Returns None if the value is not a number,
+/// comparison or range.
+fn credits_filter(value: &str) -> Option<String> {
+    // Each bound is a number and whether the bound itself is included.
+    let number = |bound: &str| bound.parse::<f64>().ok().map(|_| bound.to_owned());
+    let (low, high) = if let Some((low, high)) = value.split_once("..") {
+        let bound = |bound: &str| match bound {
+            "" => Some(None),
+            bound => number(bound).map(|bound| Some((bound, true))),
+        };
+        (bound(low)?, bound(high)?)
+    } else if let Some(low) = value.strip_prefix(">=") {
+        (Some((number(low)?, true)), None)
+    } else if let Some(low) = value.strip_prefix('>') {
+        (Some((number(low)?, false)), None)
+    } else if let Some(high) = value.strip_prefix("<=") {
+        (None, Some((number(high)?, true)))
+    } else if let Some(high) = value.strip_prefix('<') {
+        (None, Some((number(high)?, false)))
+    } else {
+        let credits = number(value)?;
+        (Some((credits.clone(), true)), Some((credits, true)))
+    };
+    let mut clauses = vec![];
+    if let Some((low, inclusive)) = low {
+        clauses.push(match inclusive {
+            true => format!("+credits_max:[{low} TO *]"),
+            false => format!("+credits_max:{{{low} TO *}}"),
+        });
+    }
+    if let Some((high, inclusive)) = high {
+        clauses.push(match inclusive {
+            true => format!("+credits_min:[* TO {high}]"),
+            false => format!("+credits_min:{{* TO {high}}}"),
+        });
+    }
+    (!clauses.is_empty()).then(|| clauses.join(" "))
+}
+
+/// Quotes every word that does not parse as a query on its own, so that
+/// stray quotes, brackets or unknown fields are searched for literally
+/// while well formed filters such as `dept:cs` keep working.
+fn escape_query(query_parser: &QueryParser, query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| {
+            let word = prepare_query(word);
+            match query_parser.parse_query(&word) {
+                Ok(_) => word,
+                Err(_) => format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\"")),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a cleaned class ID into its department code and number.
+fn split_id(id: &str) -> (&str, &str) {
+    let split = id.find(|c: char| c.is_ascii_digit()).unwrap_or(id.len());
+    id.split_at(split)
+}
+
+/// Returns the number of single character insertions, deletions,
+/// substitutions and adjacent swaps needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_source::read_classes;
+
+    /// Indexes the classes in tests/fixtures/catalog.json in memory.
+    fn fixture_index() -> (Index, QueryParser) {
+        let path = format!("{}/tests/fixtures/catalog.json", env!("CARGO_MANIFEST_DIR"));
+        let schema = index_schema();
+        let index = Index::create_in_ram(schema.clone());
+        let mut writer = index.writer(15_000_000).unwrap();
+        for class in read_classes(&path).unwrap() {
+            writer
+                .add_document(class_document(&schema, &class))
+                .unwrap();
+        }
+        writer.commit().unwrap();
+        let fields = vec![
+            schema.get_field("title").unwrap(),
+            schema.get_field("body").unwrap(),
+        ];
+        let parser = QueryParser::for_index(&index, fields);
+        (index, parser)
+    }
+
+    /// Returns a catalog of the classes in tests/fixtures/catalog.json.
+    fn fixture_catalog() -> Catalog {
+        let path = format!("{}/tests/fixtures/catalog.json", env!("CARGO_MANIFEST_DIR"));
+        Catalog::in_memory("2023-2024", read_classes(&path).unwrap())
+    }
+
+    /// Returns the IDs of the classes matching a parsed query, sorted.
+    fn matches(index: &Index, parser: &QueryParser, query: &str) -> Vec<String> {
+        let query = parser
+            .parse_query(query)
+            .unwrap_or_else(|why| panic!("{query} does not parse: {why}"));
+        let searcher = index.reader().unwrap().searcher();
+        let id = index.schema().get_field("id").unwrap();
+        let mut ids = searcher
+            .search(&query, &TopDocs::with_limit(10))
+            .unwrap()
+            .into_iter()
+            .map(|(_, address)| {
+                let document = searcher.doc(address).unwrap();
+                document
+                    .get_first(id)
+                    .unwrap()
+                    .as_text()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn index_generations_are_swapped_in_once_built() {
+        let root = std::env::temp_dir().join(format!("classy-index-{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(root);
+        let path = format!("{}/tests/fixtures/catalog.json", env!("CARGO_MANIFEST_DIR"));
+        let mut classes = read_classes(&path).unwrap();
+        let schema = index_schema();
+        let count = |index: &Index| index.reader().unwrap().searcher().num_docs();
+
+        let first = build_index(root, &schema, &classes).unwrap();
+        classes.pop();
+        let second = build_index(root, &schema, &classes).unwrap();
+        // The first generation still serves searches after the second is built.
+        assert_eq!((count(&first), count(&second)), (3, 2));
+        // Unchanged classes reopen the current generation.
+        build_index(root, &schema, &classes).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(format!("{root}/current")).unwrap(),
+            "1"
+        );
+        std::fs::create_dir_all(format!("{root}/9")).unwrap();
+        classes.pop();
+        build_index(root, &schema, &classes).unwrap();
+
+        let current = std::fs::read_to_string(format!("{root}/current")).unwrap();
+        let mut entries = std::fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(current, "2");
+        assert_eq!(entries, ["1", "2", "current"]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn typos_suggest_the_closest_class() {
+        let catalog = fixture_catalog();
+        let suggest = |id| {
+            catalog
+                .suggest(id, 3)
+                .iter()
+                .map(|class| class.id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            suggest("CS 1150").first().map(String::as_str),
+            Some("CS 115")
+        );
+        assert_eq!(
+            suggest("SC 115").first().map(String::as_str),
+            Some("CS 115")
+        );
+        assert_eq!(
+            suggest("cs-385").first().map(String::as_str),
+            Some("CS 385")
+        );
+        assert!(suggest("CS").is_empty());
+        assert!(suggest("").is_empty());
+        assert_eq!(edit_distance("CS115", "SC115"), 1);
+        assert_eq!(edit_distance("1150", "115"), 1);
+    }
+
+    #[test]
+    fn filters_become_required_clauses_and_ranges() {
+        assert_eq!(prepare_query("dept:cs compilers"), "+dept:cs compilers");
+        assert_eq!(prepare_query("level:300..499"), "+level:[300 TO 499]");
+        assert_eq!(prepare_query("credits:3.."), "+credits_max:[3 TO *]");
+        assert_eq!(
+            prepare_query("credits:1..2"),
+            "+credits_max:[1 TO *] +credits_min:[* TO 2]"
+        );
+        assert_eq!(prepare_query("credits:<3"), "+credits_min:{* TO 3}");
+        assert_eq!(prepare_query("LEVEL:..199"), "+LEVEL:[* TO 199]");
+        assert_eq!(prepare_query("title:algorithms"), "title:algorithms");
+        assert_eq!(prepare_query("level:low..high"), "+level:low..high");
+    }
+
+    #[test]
+    fn filters_parse_and_filter_with_tantivy() {
+        let (index, parser) = fixture_index();
+        let search = |query: &str| matches(&index, &parser, &prepare_query(query));
+        assert_eq!(search("dept:cs"), ["CS 115", "CS 385"]);
+        assert_eq!(search("level:300..499"), ["CS 385"]);
+        assert_eq!(search("level:..199 dept:cs"), ["CS 115"]);
+        // Variable credit classes match by any credits in their range.
+        assert_eq!(search("credits:>=3"), ["CS 115", "CS 385", "HUM 103"]);
+        assert_eq!(search("credits:>3"), ["CS 115", "CS 385"]);
+        assert_eq!(search("credits:..0"), ["HUM 103"]);
+        assert_eq!(search("credits:1..2"), ["HUM 103"]);
+        assert_eq!(search("credits:2"), ["HUM 103"]);
+        assert_eq!(search("credits:<4"), ["HUM 103"]);
+        assert_eq!(search("credits:4..5"), ["CS 115", "CS 385"]);
+        assert_eq!(search("offered:spring"), ["CS 115"]);
+        // Other words only rank the classes that pass the filters.
+        assert_eq!(search("dept:cs writing"), ["CS 115", "CS 385"]);
+    }
+
+    #[test]
+    fn escaped_queries_always_parse() {
+        let (index, parser) = fixture_index();
+        for query in [
+            r#"algorithms "unclosed"#,
+            "(writing",
+            "writing)",
+            "nosuchfield:x",
+            "[brackets",
+            r#"back\slash" quote"#,
+            "boost^ +- ~",
+            "level:abc credits:>=",
+        ] {
+            assert!(
+                parser.parse_query(&prepare_query(query)).is_err(),
+                "{query}"
+            );
+            let escaped = escape_query(&parser, query);
+            assert!(
+                parser.parse_query(&escaped).is_ok(),
+                "{query} escapes to {escaped}, which does not parse"
+            );
+        }
+        let search = |query: &str| matches(&index, &parser, &escape_query(&parser, query));
+        // Well formed words and filters are left alone.
+        assert_eq!(escape_query(&parser, "dept:cs writing"), "+dept:cs writing");
+        assert_eq!(search("dept:cs (algorithms"), ["CS 115", "CS 385"]);
+        assert_eq!(search("(algorithms"), ["CS 385"]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::departments::DepartmentNames;
+use crate::prerequisite::Prerequisite;
 pub use crate::traits::Class as ClassTrait;
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +12,8 @@ pub struct Class {
     description: String,
     credits: String,
     prerequisites: String,
+    #[serde(default)]
+    prerequisite_tree: Option<Prerequisite>,
     offered: Vec<String>,
     cross_listings: Vec<String>,
     distributions: Vec<String>,
@@ -17,6 +21,7 @@ pub struct Class {
 }
 
 impl Class {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         department: String,
         department_name: String,
@@ -25,6 +30,7 @@ impl Class {
         description: String,
         credits: String,
         prerequisites: String,
+        prerequisite_tree: Option<Prerequisite>,
         offered: Vec<String>,
         cross_listings: Vec<String>,
         distributions: Vec<String>,
@@ -38,12 +44,55 @@ impl Class {
             description,
             credits,
             prerequisites,
+            prerequisite_tree,
             offered,
             cross_listings,
             distributions,
             url,
         }
     }
+    /// Renames the class's department if it is among the given names,
+    /// returning whether its name changed.
+    pub fn rename_department(&mut self, names: &DepartmentNames) -> bool {
+        match names.get(&self.department) {
+            Some(name) if name != self.department_name => {
+                self.department_name = name.to_owned();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether a class is meant for undergraduate or graduate students.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Undergraduate,
+    Graduate,
+}
+
+impl Class {
+    /// Returns the level of the class from its number,
+    /// where 500 and above are graduate classes.
+    pub fn level(&self) -> Level {
+        match self.discriminator().parse::<u32>() {
+            Ok(number) if number >= 500 => Level::Graduate,
+            _ => Level::Undergraduate,
+        }
+    }
+    /// Returns the lowest and highest number of credits the class can be
+    /// taken for, such as (3, 3) for "3" or (0, 3) for "0-3".
+    /// Returns None if the credits are not numeric.
+    pub fn credit_range(&self) -> Option<(f64, f64)> {
+        let numbers = self
+            .credits
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .filter_map(|number| number.parse::<f64>().ok())
+            .collect::<Vec<_>>();
+        let low = numbers.iter().copied().reduce(f64::min)?;
+        let high = numbers.iter().copied().reduce(f64::max)?;
+        Some((low, high))
+    }
 }
 
 impl ClassTrait for Class {
@@ -71,6 +120,12 @@ impl ClassTrait for Class {
     fn prerequisites(&self) -> String {
         self.prerequisites.clone()
     }
+    fn prerequisite_tree(&self) -> Option<Prerequisite> {
+        // Classes cached before the tree existed only carry the raw text.
+        self.prerequisite_tree
+            .clone()
+            .or_else(|| Prerequisite::from_text(&self.prerequisites))
+    }
     fn offered(&self) -> Vec<String> {
         self.offered.clone()
     }
@@ -0,0 +1,91 @@
+use crate::class::Class;
+use crate::traits::SourceError;
+use std::collections::HashMap;
+use std::io;
+
+/// Department names by department code, such as "Computer Science"
+/// for "CS", either found in a catalog's department headings or read
+/// from a file of names that replace them.
+#[derive(Default, Debug)]
+pub struct DepartmentNames(HashMap<String, String>);
+
+impl DepartmentNames {
+    /// Reads a JSON object of department codes to names, such as
+    /// `{"HLI": "Humanities: Italian"}`. A missing file holds no names
+    /// unless `required`, for when the file was asked for by name.
+    pub fn load(path: &str, required: bool) -> Result<DepartmentNames, SourceError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(why) if why.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(DepartmentNames::default())
+            }
+            Err(why) => return Err(why.into()),
+        };
+        let names = serde_json::from_str::<HashMap<String, String>>(&text)
+            .map_err(|why| SourceError::Format {
+                path: path.to_owned(),
+                reason: why.to_string(),
+            })?
+            .into_iter()
+            .collect::<DepartmentNames>();
+        println!("Loaded {} department names from {path}.", names.0.len());
+        Ok(names)
+    }
+    /// Returns the name of the department with the given code, ignoring case.
+    pub fn get(&self, code: &str) -> Option<&str> {
+        self.0.get(&code.to_uppercase()).map(String::as_str)
+    }
+    /// Adds the given names, replacing the names of departments already named.
+    pub fn extend(&mut self, names: &DepartmentNames) {
+        self.0.extend(names.0.clone());
+    }
+    /// Renames the departments of the given classes, returning
+    /// whether any of them were renamed.
+    pub fn rename(&self, classes: &mut [Class]) -> bool {
+        let mut renamed = false;
+        for class in classes {
+            renamed |= class.rename_department(self);
+        }
+        renamed
+    }
+}
+
+impl FromIterator<(String, String)> for DepartmentNames {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(names: I) -> Self {
+        DepartmentNames(
+            names
+                .into_iter()
+                .map(|(code, name)| (code.to_uppercase(), name))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::ClassTrait;
+
+    #[test]
+    fn shipped_names_load() {
+        let names = DepartmentNames::load("./departments.json", true).unwrap();
+        assert_eq!(names.get("hli"), Some("Humanities: Italian"));
+        assert_eq!(names.get("HSS"), None);
+        assert!(DepartmentNames::load("./missing-departments.json", false).is_ok());
+        assert!(DepartmentNames::load("./missing-departments.json", true).is_err());
+    }
+
+    #[test]
+    fn classes_are_renamed_by_code() {
+        let mut classes = crate::file_source::read_classes("tests/fixtures/catalog.json").unwrap();
+        let names = [(String::from("cs"), String::from("Computing"))]
+            .into_iter()
+            .collect::<DepartmentNames>();
+        assert!(names.rename(&mut classes));
+        assert!(classes
+            .iter()
+            .filter(|class| class.department() == "CS")
+            .all(|class| class.department_name() == "Computing"));
+        assert!(!names.rename(&mut classes));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::catalog::*;
+use crate::class::*;
+use std::collections::BTreeMap;
+
+/// The differences between two catalogs, ordered by class ID.
+pub struct CatalogDiff<'a> {
+    /// Classes only in the newer catalog.
+    pub added: Vec<&'a Class>,
+    /// Classes only in the older catalog.
+    pub removed: Vec<&'a Class>,
+    /// Classes in both catalogs with at least one changed field.
+    pub changed: Vec<ClassChanges<'a>>,
+}
+
+pub struct ClassChanges<'a> {
+    /// The class as it is in the newer catalog.
+    pub class: &'a Class,
+    pub fields: Vec<FieldChange>,
+}
+
+pub struct FieldChange {
+    /// The human readable name of the field, such as "Credits".
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+impl CatalogDiff<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares the classes of two catalogs, limited to a department
+/// if one is given (an empty department compares every class).
+pub fn diff<'a>(before: &'a Catalog, after: &'a Catalog, department: &str) -> CatalogDiff<'a> {
+    let by_id = |catalog: &'a Catalog| {
+        catalog
+            .query_by_department(department)
+            .into_iter()
+            .map(|class| (class.id(), class))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let before = by_id(before);
+    let after = by_id(after);
+    let added = after
+        .iter()
+        .filter(|(id, _)| !before.contains_key(*id))
+        .map(|(_, class)| *class)
+        .collect();
+    let removed = before
+        .iter()
+        .filter(|(id, _)| !after.contains_key(*id))
+        .map(|(_, class)| *class)
+        .collect();
+    let changed = before
+        .iter()
+        .filter_map(|(id, before)| {
+            let after = after.get(id)?;
+            let fields = field_changes(before, after);
+            match fields.is_empty() {
+                true => None,
+                false => Some(ClassChanges {
+                    class: after,
+                    fields,
+                }),
+            }
+        })
+        .collect();
+    CatalogDiff {
+        added,
+        removed,
+        changed,
+    }
+}
+
+fn field_changes(before: &Class, after: &Class) -> Vec<FieldChange> {
+    let fields = [
+        ("Title", before.title(), after.title()),
+        ("Credits", before.credits(), after.credits()),
+        (
+            "Prerequisites",
+            before.prerequisites(),
+            after.prerequisites(),
+        ),
+        (
+            "Offered",
+            before.offered().join(", "),
+            after.offered().join(", "),
+        ),
+        (
+            "Distributions",
+            before.distributions().join(", "),
+            after.distributions().join(", "),
+        ),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(field, before, after)| match before == after {
+            true => None,
+            false => Some(FieldChange {
+                field,
+                before,
+                after,
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(department: &str, number: &str, title: &str, credits: &str) -> Class {
+        Class::new(
+            department.to_owned(),
+            String::new(),
+            number.to_owned(),
+            title.to_owned(),
+            String::new(),
+            credits.to_owned(),
+            String::new(),
+            None,
+            vec![String::from("Fall")],
+            vec![],
+            vec![],
+            String::new(),
+        )
+    }
+
+    #[test]
+    fn added_removed_and_changed_classes_are_found() {
+        let before = Catalog::in_memory(
+            "2023-2024",
+            vec![
+                class("CS", "115", "Introduction to Computer Science", "4"),
+                class("CS", "135", "Discrete Structures", "4"),
+                class("MA", "121", "Differential Calculus", "4"),
+            ],
+        );
+        let after = Catalog::in_memory(
+            "2024-2025",
+            vec![
+                class("CS", "115", "Introduction to Computer Science", "3"),
+                class("CS", "284", "Data Structures", "4"),
+                class("MA", "121", "Differential Calculus", "4"),
+                class("MA", "122", "Integral Calculus", "4"),
+            ],
+        );
+        let ids = |classes: &[&Class]| classes.iter().map(|c| c.id()).collect::<Vec<_>>();
+
+        let changes = diff(&before, &after, "");
+        assert_eq!(ids(&changes.added), ["CS 284", "MA 122"]);
+        assert_eq!(ids(&changes.removed), ["CS 135"]);
+        assert_eq!(changes.changed.len(), 1);
+        assert_eq!(changes.changed[0].class.id(), "CS 115");
+        let fields = &changes.changed[0].fields;
+        assert_eq!(fields.len(), 1);
+        assert_eq!(
+            (
+                fields[0].field,
+                fields[0].before.as_str(),
+                fields[0].after.as_str()
+            ),
+            ("Credits", "4", "3")
+        );
+
+        let changes = diff(&before, &after, "ma");
+        assert_eq!(ids(&changes.added), ["MA 122"]);
+        assert!(changes.removed.is_empty() && changes.changed.is_empty());
+        assert!(diff(&after, &after, "").is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::class::*;
+use crate::departments::DepartmentNames;
+use crate::traits::{CatalogSource, SourceError};
+use async_trait::async_trait;
+use std::path::Path;
+use tokio::sync::watch;
+
+/// The CSV columns a class is read from. Only the department, number and
+/// title are required, and list columns separate their items with `;`.
+const CSV_COLUMNS: [&str; 11] = [
+    "department",
+    "department_name",
+    "number",
+    "title",
+    "description",
+    "credits",
+    "prerequisites",
+    "offered",
+    "cross_listings",
+    "distributions",
+    "url",
+];
+
+/// Reads classes from local files instead of a school's site, with one
+/// file per academic year found through a path template such as
+/// "./catalogs/{year}.json". JSON files hold a list of classes in the
+/// same form as the cache, while CSV files use the `CSV_COLUMNS` header.
+pub struct FileSource {
+    template: String,
+    /// Names that replace the department names in the files.
+    department_names: DepartmentNames,
+}
+
+impl FileSource {
+    pub fn new(template: &str, department_names: DepartmentNames) -> Self {
+        Self {
+            template: template.to_owned(),
+            department_names,
+        }
+    }
+    /// Returns the path of the file holding an academic year's classes.
+    fn path(&self, year: &str) -> String {
+        self.template.replace("{year}", year)
+    }
+}
+
+#[async_trait]
+impl CatalogSource for FileSource {
+    async fn load(&self, year: &str) -> Result<Vec<Class>, SourceError> {
+        let path = self.path(year);
+        println!("Reading classes from {path}...");
+        let mut classes = read_classes(&path)?;
+        self.department_names.rename(&mut classes);
+        Ok(classes)
+    }
+    /// Reads the file again, since it may have been edited.
+    async fn refresh(
+        &self,
+        year: &str,
+        _progress: Option<&watch::Sender<String>>,
+    ) -> Result<Vec<Class>, SourceError> {
+        self.load(year).await
+    }
+}
+
+/// Reads the classes in a JSON or CSV file, told apart by its extension.
+pub fn read_classes(path: &str) -> Result<Vec<Class>, SourceError> {
+    let format_error = |reason: String| SourceError::Format {
+        path: path.to_owned(),
+        reason,
+    };
+    let text = std::fs::read_to_string(path)?;
+    match Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("json") => serde_json::from_str(&text).map_err(|why| format_error(why.to_string())),
+        Some("csv") => classes_from_csv(&text).map_err(format_error),
+        _ => Err(format_error(String::from("expected a .json or .csv file"))),
+    }
+}
+
+fn classes_from_csv(text: &str) -> Result<Vec<Class>, String> {
+    let mut rows = csv_rows(text)?.into_iter();
+    let header = rows.next().ok_or("the file is empty")?;
+    let columns = CSV_COLUMNS.map(|name| header.iter().position(|column| column.trim() == name));
+    for required in ["department", "number", "title"] {
+        let index = CSV_COLUMNS
+            .iter()
+            .position(|name| *name == required)
+            .unwrap();
+        if columns[index].is_none() {
+            return Err(format!("the header has no {required} column"));
+        }
+    }
+    rows.enumerate()
+        .map(|(line, row)| {
+            let [department, department_name, number, title, description, credits, prerequisites, offered, cross_listings, distributions, url] =
+                columns.map(|column| {
+                    column
+                        .and_then(|column| row.get(column))
+                        .map(|value| value.trim().to_owned())
+                        .unwrap_or_default()
+                });
+            if department.is_empty() || number.is_empty() {
+                return Err(format!("row {} has no department or number", line + 2));
+            }
+            Ok(Class::new(
+                department.to_uppercase(),
+                department_name,
+                number,
+                title,
+                description,
+                credits,
+                prerequisites,
+                None,
+                list(&offered),
+                list(&cross_listings),
+                list(&distributions),
+                url,
+            ))
+        })
+        .collect()
+}
+
+/// Splits a list column into its items.
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Splits CSV text into rows of fields, following RFC 4180 quoting
+/// and skipping blank lines.
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            // Line breaks in quoted fields are kept as plain newlines.
+            (true, '\r') if chars.peek() == Some(&'\n') => {}
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(String::from("a quoted field is never closed"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|row| !(row.len() == 1 && row[0].trim().is_empty()));
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[test]
+    fn json_and_csv_files_hold_the_same_classes() {
+        let json = read_classes(&fixture("catalog.json")).unwrap();
+        let csv = read_classes(&fixture("catalog.csv")).unwrap();
+        assert_eq!(json.len(), 3);
+        assert_eq!(
+            serde_json::to_value(&json).unwrap(),
+            serde_json::to_value(&csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn csv_without_required_columns_is_rejected() {
+        let error = classes_from_csv("department,title\nCS,Compilers\n").unwrap_err();
+        assert_eq!(error, "the header has no number column");
+        let error = classes_from_csv("department,number,title\n\"CS,115,Intro\n").unwrap_err();
+        assert_eq!(error, "a quoted field is never closed");
+    }
+}
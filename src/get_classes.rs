@@ -1,70 +1,365 @@
-// use futures::stream::*;
+use futures::stream::{self, StreamExt};
 // use heck::ToTitleCase;
 // use indicatif::ProgressBar;
+use crate::cache::Cache;
 use crate::class::*;
-use reqwest::Client;
+use crate::departments::DepartmentNames;
+use crate::prerequisite::Prerequisite;
+use crate::traits::{CatalogSource, SourceError};
+use async_trait::async_trait;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, RequestBuilder, StatusCode};
 use scraper::ElementRef;
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 // use serde_json;
 // use std::collections::BTreeSet;
-// use std::collections::HashMap;
 use thiserror::Error;
+use tokio::sync::watch;
+use tokio::time::Instant;
 // use tokio::fs::File;
 // use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-// const API_LINK: &str = "https://stevens.smartcatalogiq.com/Institutions/Stevens-Institution-of-Technology/json/2023-2024/Academic-Catalog.json";
-const CLASSES_PAGE: &str =
-    "https://stevens.smartcatalogiq.com/en/2023-2024/academic-catalog/courses/";
+/// Bumped whenever parsing changes, so that classes cached
+/// by older versions are parsed again from their responses.
+pub const SCRAPER_VERSION: u32 = 6;
+
+/// The host of Stevens' catalog, used unless another school's is given.
+pub const STEVENS_HOST: &str = "stevens.smartcatalogiq.com";
+/// The institution Stevens' catalog is published under in the JSON API.
+pub const STEVENS_INSTITUTION: &str = "Stevens-Institution-of-Technology";
+
+/// Returns the page listing every course in the given academic year's catalog.
+fn classes_page(host: &str, year: &str) -> String {
+    format!("https://{host}/en/{year}/academic-catalog/courses/")
+}
+
+/// Returns the JSON tree of every section in the given academic year's catalog.
+fn catalog_api_page(host: &str, institution: &str, year: &str) -> String {
+    format!("https://{host}/Institutions/{institution}/json/{year}/Academic-Catalog.json")
+}
+
+/// Scrapes classes from a school's SmartCatalog site,
+/// caching the pages and the classes parsed from them.
+pub struct SmartCatalog {
+    /// The site's host, such as "stevens.smartcatalogiq.com".
+    host: String,
+    /// The institution the catalog's JSON API is published under,
+    /// or None to only find classes through the courses page.
+    institution: Option<String>,
+    /// Names that replace the department names in the catalog.
+    department_names: DepartmentNames,
+    /// The cache of each academic year that was loaded, locked
+    /// while the year is loaded or refreshed.
+    caches: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Cache>>>>,
+}
+
+impl SmartCatalog {
+    pub fn new(host: &str, institution: Option<&str>, department_names: DepartmentNames) -> Self {
+        Self {
+            host: host.to_owned(),
+            institution: institution.map(str::to_owned),
+            department_names,
+            caches: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Returns the cache of an academic year, opening it the first time.
+    fn cache(&self, year: &str) -> io::Result<Arc<tokio::sync::Mutex<Cache>>> {
+        let mut caches = self.caches.lock().unwrap();
+        if let Some(cache) = caches.get(year) {
+            return Ok(cache.clone());
+        }
+        println!("Opening the cache in ./cache/{year}...");
+        let cache = Arc::new(tokio::sync::Mutex::new(Cache::open(year)?));
+        caches.insert(year.to_owned(), cache.clone());
+        Ok(cache)
+    }
+    /// Lists every class in the given academic year's catalog through
+    /// the JSON API if possible and otherwise through the list on the
+    /// courses page, which has no department headings. The department
+    /// names the catalog was given replace the ones in the headings.
+    async fn list_classes(&self, year: &str) -> Result<ClassListing, ClassQueryError> {
+        let client = ScrapeConfig::from_env().client();
+        let mut listing = None;
+        if let Some(institution) = &self.institution {
+            match query_catalog_api(&client, &self.host, institution, year).await {
+                Ok(found) => listing = Some(found),
+                Err(why) => println!("Falling back to the courses page: {why}"),
+            }
+        }
+        let mut listing = match listing {
+            Some(listing) => listing,
+            None => {
+                let text = query_classes_page(&client, &self.host, year).await?;
+                ClassListing {
+                    links: listed_class_links(&self.host, &text),
+                    departments: DepartmentNames::default(),
+                }
+            }
+        };
+        listing.departments.extend(&self.department_names);
+        Ok(listing)
+    }
+    /// Lists the classes like `list_classes`, retrying with exponential
+    /// backoff until every attempt has failed.
+    async fn list_classes_retrying(
+        &self,
+        year: &str,
+        config: &ScrapeConfig,
+    ) -> Result<ClassListing, ClassQueryError> {
+        let mut backoff = config.backoff;
+        let mut attempt = 1;
+        loop {
+            match self.list_classes(year).await {
+                Ok(listing) => return Ok(listing),
+                Err(why) if attempt >= config.attempts => return Err(why),
+                Err(why) => {
+                    println!("Listing the classes again in {backoff:?} after: {why}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The classes in a catalog and the departments they are listed under.
+struct ClassListing {
+    links: Vec<String>,
+    /// The names in the department headings, used to name
+    /// the department of each parsed class.
+    departments: DepartmentNames,
+}
+
+#[async_trait]
+impl CatalogSource for SmartCatalog {
+    /// Loads the cached classes, or parses them from the cached
+    /// responses after querying the missing ones if the cached
+    /// classes are incomplete. Failed queries are retried for a
+    /// few rounds before leaving their classes out of the catalog.
+    async fn load(&self, year: &str) -> Result<Vec<Class>, SourceError> {
+        let cache = self.cache(year)?;
+        let mut cache = cache.lock().await;
+        if let Some(mut classes) = cache.complete_classes()? {
+            println!("Loaded {} cached classes.", classes.len());
+            // Department names may have been corrected since.
+            if self.department_names.rename(&mut classes) {
+                cache.replace_classes(&classes)?;
+            }
+            return Ok(classes);
+        }
+        let mut responses = cache.responses()?;
+        println!("Loaded {} cached responses.", responses.len());
+
+        println!("Checking for missing links in cached responses...");
+        let config = ScrapeConfig::from_env();
+        let mut report = ParseReport::default();
+        let mut round = 0;
+        let departments = loop {
+            round += 1;
+            let listing = self.list_classes_retrying(year, &config).await?;
+            let query = query_classes(listing.links, &responses, None).await;
+            let mut failures = vec![];
+            for response in query {
+                let response = match response {
+                    Ok(response) => response,
+                    // Classes removed from the catalog are dropped
+                    // from the cache instead of failing every query.
+                    Err(ClassQueryError::CachedLinkNotFound { cached_link }) => {
+                        responses.retain(|r| r.link != cached_link);
+                        cache.remove_response(&cached_link)?;
+                        continue;
+                    }
+                    Err(why) => {
+                        failures.push(why);
+                        continue;
+                    }
+                };
+                if responses.iter().filter(|r| r.link == response.link).count() == 0 {
+                    cache.write_response(&response)?;
+                    responses.push(response);
+                }
+            }
+            // Queried classes are passed back in as cached,
+            // so only the failed ones are queried again.
+            if failures.is_empty() {
+                break listing.departments;
+            }
+            // A few classes that keep failing shouldn't keep the
+            // rest of the catalog from loading.
+            if round == config.rounds {
+                println!(
+                    "Giving up on {} classes after {round} rounds of queries.",
+                    failures.len()
+                );
+                report.query_errors = failures.iter().map(ToString::to_string).collect();
+                break listing.departments;
+            }
+            println!("Querying {} classes that failed again...", failures.len());
+        };
+
+        println!("Parsing responses into Class objects...");
+        let classes = responses
+            .into_iter()
+            .filter_map(|response| report.parse(response, &departments))
+            .collect::<Vec<_>>();
+        println!(
+            "Parsed {} classes, failing to parse {} pages.",
+            classes.len(),
+            report.errors.len()
+        );
+        cache.write_parse_report(&report)?;
+        // Without every class, the next startup parses the
+        // responses again after querying the classes that failed.
+        if report.query_errors.is_empty() {
+            cache.replace_classes(&classes)?;
+            println!("Wrote classes to the cache.");
+        }
+        Ok(classes)
+    }
+    /// Queries every page again, only downloading and parsing pages
+    /// that changed since they were cached, then replaces the cached
+    /// responses and classes. Unlike `load`, gives up after one
+    /// round instead of querying the classes that failed again.
+    async fn refresh(
+        &self,
+        year: &str,
+        progress: Option<&watch::Sender<String>>,
+    ) -> Result<Vec<Class>, SourceError> {
+        let cache = self.cache(year)?;
+        let mut cache = cache.lock().await;
+        let cached_pages = cache.responses()?;
+        let mut cached_classes = cache
+            .classes()?
+            .into_iter()
+            .map(|class| (class.url(), class))
+            .collect::<HashMap<_, _>>();
+
+        println!("Querying every class in the {year} catalog...");
+        let listing = self.list_classes(year).await?;
+        let mut pages = vec![];
+        let mut classes = vec![];
+        let mut report = ParseReport::default();
+        let mut modified = 0;
+        // Every class is queried before giving up, but a partial
+        // catalog would drop the failed classes from the cache.
+        let mut failures = vec![];
+        for response in revalidate_classes(listing.links, &cached_pages, progress).await {
+            let response = match response {
+                Ok(response) => response,
+                Err(why) => {
+                    failures.push(why);
+                    continue;
+                }
+            };
+            // Unchanged pages keep the class parsed from them before.
+            let cached = match response.modified {
+                true => {
+                    modified += 1;
+                    None
+                }
+                false => cached_classes.remove(&response.page.link),
+            };
+            match cached {
+                Some(mut class) => {
+                    class.rename_department(&self.department_names);
+                    classes.push(class)
+                }
+                None => classes.extend(report.parse(response.page.clone(), &listing.departments)),
+            }
+            pages.push(response.page);
+        }
+        if let Some(why) = failures.into_iter().next() {
+            return Err(why.into());
+        }
+        println!(
+            "{modified} of {} pages changed, leaving {} classes and {} pages that failed to parse.",
+            pages.len(),
+            classes.len(),
+            report.errors.len()
+        );
+        cache.write_parse_report(&report)?;
+
+        cache.replace_responses(&pages)?;
+        cache.replace_classes(&classes)?;
+        println!("Replaced cached responses and classes in ./cache/{year}.");
+        Ok(classes)
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum ClassQueryError {
-    #[error("")]
+    #[error("{source}")]
     Reqwest {
         #[from]
         source: reqwest::Error,
     },
-    #[error("")]
+    #[error("cached link {cached_link} is no longer in the catalog")]
     CachedLinkNotFound { cached_link: String },
+    #[error("{link} responded with {status}")]
+    Status { link: String, status: StatusCode },
+    #[error("{link} has no courses section")]
+    NoCourses { link: String },
+}
+
+impl From<ClassQueryError> for SourceError {
+    fn from(why: ClassQueryError) -> Self {
+        SourceError::Query {
+            source: Box::new(why),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ClassPage {
     pub link: String,
     pub text: String,
+    /// The validators the page was served with, sent back when
+    /// querying it again to only download it if it changed.
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// When the page was last queried, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub fetched_at: Option<u64>,
 }
 
-/// Queries classes from the provided api link
-/// and returns a vec of the response texts,
-/// returning early if an error is added to
-/// the vec.
-pub async fn query_classes(cache: &Vec<ClassPage>) -> Vec<Result<ClassPage, ClassQueryError>> {
-    // let mut links = match query_class_links().await {
-    //     Ok(value) => value,
-    //     Err(why) => return vec![Err(why)],
-    // };
-    let classes_page = Html::parse_document(
-        reqwest::get(CLASSES_PAGE)
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap()
-            .as_str(),
-    );
-    let class_nodes = classes_page
-        .select(&Selector::parse("#main > ul:nth-child(3) > li").unwrap())
-        .collect::<Vec<_>>();
-    let mut links = class_nodes
-        .iter()
-        .map(|t| {
-            format!(
-                "https://stevens.smartcatalogiq.com{}/",
-                t.inner_html().split("\"").nth(1).unwrap().to_lowercase()
-            )
-        })
-        .collect::<Vec<_>>();
+impl ClassPage {
+    /// Adds conditional headers to a request for this page,
+    /// so that an unchanged page gets a 304 response.
+    fn conditional(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = match &self.etag {
+            Some(etag) => request.header(IF_NONE_MATCH, etag),
+            None => request,
+        };
+        match &self.last_modified {
+            Some(last_modified) => request.header(IF_MODIFIED_SINCE, last_modified),
+            None => request,
+        }
+    }
+}
 
+/// A queried class page and whether it changed since it was cached.
+pub struct QueriedPage {
+    pub page: ClassPage,
+    pub modified: bool,
+}
+
+/// Queries the linked classes that aren't cached and returns
+/// a vec of the response texts, with an error for each class
+/// that could not be queried. Progress lines are also sent
+/// to `progress` if given.
+async fn query_classes(
+    mut links: Vec<String>,
+    cache: &Vec<ClassPage>,
+    progress: Option<&watch::Sender<String>>,
+) -> Vec<Result<ClassPage, ClassQueryError>> {
     let mut responses = Vec::with_capacity(links.len());
     for response in cache {
         if let Some(index) = links.iter().position(|link| *link == response.link) {
@@ -74,142 +369,440 @@ pub async fn query_classes(cache: &Vec<ClassPage>) -> Vec<Result<ClassPage, Clas
             responses.push(Err(ClassQueryError::CachedLinkNotFound {
                 cached_link: response.link.clone(),
             }));
-            return responses;
         }
     }
-    let client = Client::new();
-    let mut counter = 1;
+    let links = links.into_iter().map(|link| (link, None)).collect();
+    let queried = query_pages(links, progress).await;
+    responses.extend(
+        queried
+            .into_iter()
+            .map(|response| response.map(|queried| queried.page)),
+    );
+    responses
+}
+
+/// Queries every linked class again, asking the catalog to skip
+/// cached pages that have not changed. Unchanged pages are
+/// returned from the cache with a new fetch time.
+async fn revalidate_classes(
+    links: Vec<String>,
+    cache: &[ClassPage],
+    progress: Option<&watch::Sender<String>>,
+) -> Vec<Result<QueriedPage, ClassQueryError>> {
+    let links = links
+        .into_iter()
+        .map(|link| {
+            let cached = cache.iter().find(|page| page.link == link).cloned();
+            (link, cached)
+        })
+        .collect();
+    query_pages(links, progress).await
+}
+
+/// Queries class pages concurrently, each conditional on its cached page
+/// if one is given, and returns them in the order they finish.
+async fn query_pages(
+    links: Vec<(String, Option<ClassPage>)>,
+    progress: Option<&watch::Sender<String>>,
+) -> Vec<Result<QueriedPage, ClassQueryError>> {
+    let config = ScrapeConfig::from_env();
+    let client = config.client();
+    let limiter = RateLimiter::new(config.request_interval);
     let length = links.len();
-    for link in links {
-        println!("Querying {}: {}", length, counter);
-        match client.get(&link).send().await {
-            Ok(response) => match response.status() {
-                reqwest::StatusCode::OK => responses.push(Ok(ClassPage {
-                    link,
-                    text: response.text().await.unwrap(),
-                })),
-                _ => {}
-            },
+    let mut queries = stream::iter(links)
+        .map(|(link, cached)| query_class(&client, &limiter, &config, link, cached))
+        .buffer_unordered(config.parallel_requests);
+    let mut responses = Vec::with_capacity(length);
+    let mut counter = 0;
+    while let Some(response) = queries.next().await {
+        counter += 1;
+        let line = format!("Querying {}: {}", length, counter);
+        println!("{line}");
+        if let Some(progress) = progress {
+            progress.send_replace(line);
+        }
+        match response {
+            Ok(Some(response)) => responses.push(Ok(response)),
+            Ok(None) => {}
             Err(why) => {
-                responses.push(Err(ClassQueryError::Reqwest { source: why }));
-                return responses;
+                println!("Failed to query a class: {why}");
+                responses.push(Err(why));
             }
-        };
-        counter += 1;
+        }
     }
     responses
 }
 
-// async fn query_class_links() -> Result<Vec<String>, ClassQueryError> {
-//     let mut links = vec![];
-//     let response = reqwest::get(API_LINK).await?;
-//     let l1 = &response.json::<serde_json::Value>().await?["Children"][23];
-//     // TODO Rewrite
-//     for c1 in 0..65536 {
-//         let l2 = &l1["Children"][c1];
-//         if l2.is_null() {
-//             break;
-//         }
-//         for c2 in 0..65536 {
-//             let l3 = &l2["Children"][c2];
-//             if l3.is_null() {
-//                 break;
-//             }
-//             for c3 in 0..65536 {
-//                 let course = match &l3["Children"][c3] {
-//                     value => match value.is_null() {
-//                         true => break,
-//                         _ => value,
-//                     },
-//                 };
-//                 links.push(
-//                     "https://stevens.smartcatalogiq.com/en".to_string()
-//                         + &*course["Path"]
-//                             .as_str()
-//                             .unwrap_or_else(|| todo!())
-//                             .to_lowercase(),
-//                 );
-//             }
-//         }
-//     }
-//     Ok(links)
-// }
-
-pub fn parse_class(page: ClassPage) -> Option<Class> {
+/// Queries one class page, retrying with exponential backoff after
+/// server errors, rate limiting and timeouts. Returns None if the
+/// page is missing or otherwise not OK.
+async fn query_class(
+    client: &Client,
+    limiter: &RateLimiter,
+    config: &ScrapeConfig,
+    link: String,
+    cached: Option<ClassPage>,
+) -> Result<Option<QueriedPage>, ClassQueryError> {
+    let mut backoff = config.backoff;
+    let mut attempt = 1;
+    loop {
+        limiter.wait(&link).await;
+        let request = match &cached {
+            Some(cached) => cached.conditional(client.get(&link)),
+            None => client.get(&link),
+        };
+        let why = match request.send().await {
+            Ok(response) if response.status() == StatusCode::NOT_MODIFIED && cached.is_some() => {
+                let page = ClassPage {
+                    fetched_at: Some(unix_time()),
+                    ..cached.unwrap()
+                };
+                return Ok(Some(QueriedPage {
+                    page,
+                    modified: false,
+                }));
+            }
+            Ok(response) if response.status() == StatusCode::OK => {
+                let header = |name| {
+                    let value = response.headers().get(name)?;
+                    value.to_str().ok().map(str::to_owned)
+                };
+                let etag = header(ETAG);
+                let last_modified = header(LAST_MODIFIED);
+                match response.text().await {
+                    Ok(text) => {
+                        let page = ClassPage {
+                            link,
+                            text,
+                            etag,
+                            last_modified,
+                            fetched_at: Some(unix_time()),
+                        };
+                        return Ok(Some(QueriedPage {
+                            page,
+                            modified: true,
+                        }));
+                    }
+                    Err(why) => ClassQueryError::from(why),
+                }
+            }
+            Ok(response)
+                if response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                ClassQueryError::Status {
+                    link: link.clone(),
+                    status: response.status(),
+                }
+            }
+            Ok(_) => return Ok(None),
+            Err(why) if why.is_timeout() || why.is_connect() => ClassQueryError::from(why),
+            Err(why) => return Err(why.into()),
+        };
+        if attempt >= config.attempts {
+            return Err(why);
+        }
+        println!("Retrying {link} in {backoff:?} after: {why}");
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Limits on how hard the scraper queries the catalog.
+struct ScrapeConfig {
+    /// The most requests in flight at once, from SCRAPE_PARALLELISM.
+    parallel_requests: usize,
+    /// The least time between requests to the same host,
+    /// from SCRAPE_INTERVAL_MS.
+    request_interval: Duration,
+    /// The most times a request is tried before giving up.
+    attempts: u32,
+    /// The wait before the first retry, doubled for every later one.
+    backoff: Duration,
+    timeout: Duration,
+    /// The most times loading a catalog queries the classes that
+    /// failed every attempt again before giving up.
+    rounds: u32,
+}
+
+impl ScrapeConfig {
+    fn from_env() -> Self {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        Self {
+            parallel_requests: var("SCRAPE_PARALLELISM").map_or(8, |n| n.max(1) as usize),
+            request_interval: Duration::from_millis(var("SCRAPE_INTERVAL_MS").unwrap_or(100)),
+            attempts: 4,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+            rounds: 3,
+        }
+    }
+    /// Returns an HTTP client whose requests give up after the timeout.
+    fn client(&self) -> Client {
+        Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .expect("the HTTP client could not be built")
+    }
+}
+
+/// Spaces out requests to the same host by handing out time slots.
+struct RateLimiter {
+    interval: Duration,
+    next_slots: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_slots: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Waits until the link's host may be sent another request.
+    async fn wait(&self, link: &str) {
+        let host = reqwest::Url::parse(link)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_default();
+        let slot = {
+            let mut next_slots = self.next_slots.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slots
+                .get(&host)
+                .copied()
+                .map_or(now, |slot| slot.max(now));
+            next_slots.insert(host, slot + self.interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+async fn query_classes_page(
+    client: &Client,
+    host: &str,
+    year: &str,
+) -> Result<String, reqwest::Error> {
+    client
+        .get(classes_page(host, year))
+        .send()
+        .await?
+        .text()
+        .await
+}
+
+/// Returns the link to every class listed on the courses page. Parsed
+/// pages can't be sent between threads, so this is kept out of async code.
+fn listed_class_links(host: &str, classes_page: &str) -> Vec<String> {
+    Html::parse_document(classes_page)
+        .select(&Selector::parse("#main > ul:nth-child(3) > li").unwrap())
+        .filter_map(|t| {
+            let path = t.inner_html().split("\"").nth(1)?.to_lowercase();
+            Some(format!("https://{host}{path}/"))
+        })
+        .collect()
+}
+
+/// A section of the catalog's JSON API, such as the courses section,
+/// a department, a level within it or a single course.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CatalogNode {
+    #[serde(default)]
+    name: Option<String>,
+    /// The section's path without the language, such as
+    /// "/2023-2024/Academic-Catalog/Courses/CS-Computer-Science".
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    children: Option<Vec<CatalogNode>>,
+}
+
+impl CatalogNode {
+    fn children(&self) -> &[CatalogNode] {
+        self.children.as_deref().unwrap_or_default()
+    }
+    /// Returns whether this is the section every course is listed under.
+    fn is_courses(&self) -> bool {
+        let name = match &self.path {
+            Some(path) => path.trim_end_matches('/').rsplit('/').next(),
+            None => self.name.as_deref(),
+        };
+        name.is_some_and(|name| name.eq_ignore_ascii_case("courses"))
+    }
+    /// Returns the first section found breadth first that matches.
+    fn find(&self, matches: impl Fn(&CatalogNode) -> bool) -> Option<&CatalogNode> {
+        let mut queue = std::collections::VecDeque::from([self]);
+        while let Some(node) = queue.pop_front() {
+            if matches(node) {
+                return Some(node);
+            }
+            queue.extend(node.children());
+        }
+        None
+    }
+    /// Adds the path of every section without children below this one.
+    fn leaf_paths<'a>(&'a self, paths: &mut Vec<&'a str>) {
+        for child in self.children() {
+            match (child.children().is_empty(), &child.path) {
+                (true, Some(path)) => paths.push(path),
+                (true, None) => {}
+                (false, _) => child.leaf_paths(paths),
+            }
+        }
+    }
+}
+
+async fn query_catalog_api(
+    client: &Client,
+    host: &str,
+    institution: &str,
+    year: &str,
+) -> Result<ClassListing, ClassQueryError> {
+    let link = catalog_api_page(host, institution, year);
+    let response = client.get(&link).send().await?;
+    if !response.status().is_success() {
+        return Err(ClassQueryError::Status {
+            link,
+            status: response.status(),
+        });
+    }
+    let catalog = response.json::<CatalogNode>().await?;
+    catalog_class_links(host, &catalog).ok_or(ClassQueryError::NoCourses { link })
+}
+
+/// Lists every course in the catalog's JSON tree with links in the
+/// same form as the links on the courses page, or returns None if the
+/// tree has no courses section.
+fn catalog_class_links(host: &str, catalog: &CatalogNode) -> Option<ClassListing> {
+    let courses = catalog.find(CatalogNode::is_courses)?;
+    let mut paths = vec![];
+    courses.leaf_paths(&mut paths);
+    let links = paths
+        .into_iter()
+        .map(|path| {
+            let path = path.trim_end_matches('/').to_lowercase();
+            format!("https://{host}/en{path}/")
+        })
+        .collect::<Vec<_>>();
+    // Departments are headed by their code and name, such as
+    // "CS - Computer Science".
+    let departments = courses
+        .children()
+        .iter()
+        .filter_map(|department| department.name.as_deref()?.split_once(" - "))
+        .map(|(code, name)| (code.trim().to_owned(), name.trim().to_owned()))
+        .collect();
+    (!links.is_empty()).then_some(ClassListing { links, departments })
+}
+
+/// Why a class page could not be parsed, naming the field that
+/// failed, the selector it was looked for with and the page's link.
+#[derive(Error, Debug, Serialize)]
+pub enum ParseError {
+    #[error("{link} has no {field}: nothing matches {selector}")]
+    Missing {
+        field: &'static str,
+        selector: &'static str,
+        link: String,
+    },
+    #[error("{link} has a malformed {field} in {selector}")]
+    Malformed {
+        field: &'static str,
+        selector: &'static str,
+        link: String,
+    },
+}
+
+/// The pages that could not be queried or parsed into classes, kept
+/// in the cache so that they can be looked into without the logs.
+#[derive(Serialize, Default)]
+pub struct ParseReport {
+    /// The number of pages parsed into classes.
+    pub classes: usize,
+    pub errors: Vec<ParseError>,
+    /// Why the classes left out of the catalog failed every query.
+    pub query_errors: Vec<String>,
+}
+
+impl ParseReport {
+    /// Parses a page into its class, recording the error instead
+    /// if the page could not be parsed.
+    pub fn parse(&mut self, page: ClassPage, departments: &DepartmentNames) -> Option<Class> {
+        match parse_class(page, departments) {
+            Ok(class) => {
+                self.classes += class.is_some() as usize;
+                class
+            }
+            Err(why) => {
+                println!("Failed to parse a class: {why}");
+                self.errors.push(why);
+                None
+            }
+        }
+    }
+}
+
+/// Parses a class page, returning None for pages in the course
+/// listing that aren't classes. Departments are named by their
+/// heading in `departments` if they have one.
+pub fn parse_class(
+    page: ClassPage,
+    departments: &DepartmentNames,
+) -> Result<Option<Class>, ParseError> {
+    let link = page.link;
+    if link.contains("narrative-courses")
+        || link.contains("te-technical-elective")
+        || link.contains("hum-humanities-general")
+    {
+        return Ok(None);
+    }
+
     let html = Html::parse_document(page.text.as_str());
-    let main = match html
+    let main = html
         .select(&Selector::parse("div").unwrap())
         .find(|element| element.value().attr("id") == Some("main"))
-    {
-        Some(value) => value,
-        None => todo!(),
-    };
+        .ok_or_else(|| ParseError::Missing {
+            field: "content",
+            selector: "div#main",
+            link: link.clone(),
+        })?;
 
-    let id = match &*parse_id(&main) {
+    let id = match &*parse_id(&main, &link)? {
         "HSS HSS 317" => "HSS 317",
         "EM 347 ZZZDNU" => "EM 347",
         rest => rest,
     }
     .to_owned();
-    let name = parse_name(&main);
-    let description = parse_description(&main);
-    let credits = parse_credits(&main);
-    let cross_listed = parse_cross_listed(&main);
-    let prerequisites = parse_prerequisites(&main);
+    let name = parse_name(&main, &link)?;
+    let description = parse_description(&main, &link)?;
+    let credits = parse_credits(&main, &link)?;
+    let cross_listed = parse_cross_listed(&main, &link)?;
+    let prerequisites = parse_prerequisites(&main, &link)?;
+    let prerequisite_tree = parse_prerequisite_tree(&main);
     let offered = parse_offered(&main);
     let distribution = parse_distribution(&main);
-    let link = page.link;
-
-    if link.contains("narrative-courses")
-        || link.contains("te-technical-elective")
-        || link.contains("hum-humanities-general")
-    {
-        return None;
-    }
+    let department = id.chars().filter(|c| c.is_alphabetic()).collect::<String>();
+    let department_name = match departments.get(&department) {
+        Some(name) => name.to_owned(),
+        None => parse_department_name(&link)?,
+    };
 
-    Some(Class::new(
-        id.chars().filter(|c| c.is_alphabetic()).collect::<String>(),
-        link.split_once("/courses/")
-            .unwrap()
-            .1
-            .split_once("/")
-            .unwrap()
-            .0
-            .split_once("-")
-            .unwrap()
-            .1
-            .split("-")
-            .map(std::primitive::str::trim)
-            .map(|s| if s == "humanities" { "hplaceholder" } else { s })
-            .map(|s| match s.strip_prefix("humanities") {
-                Some(rest) => format!("humanities {}", rest),
-                None => String::from(s),
-            })
-            .map(|s| {
-                if s == "hplaceholder" {
-                    String::from("humanities")
-                } else {
-                    s
-                }
-            })
-            .map(|s| {
-                if s == "languageitalian" {
-                    String::from("language italian")
-                } else {
-                    s
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(" ")
-            .split(" ")
-            .map(|s| match s {
-                "or" | "and" | "of" | "for" => String::from(s),
-                _ => {
-                    format!("{}{}", (&s[..1].to_string()).to_uppercase(), &s[1..])
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(" "),
+    Ok(Some(Class::new(
+        department,
+        department_name,
         id.chars()
             .filter(|c| c.is_ascii_digit())
             .collect::<String>(),
@@ -217,66 +810,145 @@ pub fn parse_class(page: ClassPage) -> Option<Class> {
         description,
         credits,
         prerequisites,
+        prerequisite_tree,
         offered,
         cross_listed,
         distribution,
         link,
-    ))
+    )))
 }
 
-fn parse_id(main: &ElementRef) -> String {
-    main.select(&Selector::parse("h1").unwrap())
+/// Returns a department name from the link to a class, such as
+/// "Computer Science" for ".../courses/cs-computer-science/...",
+/// for departments without a heading in the catalog.
+fn parse_department_name(link: &str) -> Result<String, ParseError> {
+    let malformed = || ParseError::Malformed {
+        field: "department name",
+        selector: "/courses/<code>-<name>/ in the link",
+        link: link.to_owned(),
+    };
+    let department = link
+        .split_once("/courses/")
+        .ok_or_else(malformed)?
+        .1
+        .split_once("/")
+        .ok_or_else(malformed)?
+        .0
+        .split_once("-")
+        .ok_or_else(malformed)?
+        .1;
+    Ok(department
+        .split("-")
+        .filter(|word| !word.is_empty())
+        .flat_map(split_fused_words)
+        .map(|word| match word {
+            "or" | "and" | "of" | "for" => String::from(word),
+            _ => {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" "))
+}
+
+/// Words that the catalog fuses onto the word after them in department
+/// links, such as "humanitiesmusic" for "humanities music".
+const FUSED_WORDS: [&str; 1] = ["humanities"];
+
+/// Splits the fused words off the start of a word in a department link.
+fn split_fused_words(word: &str) -> Vec<&str> {
+    for fused in FUSED_WORDS {
+        if let Some(rest) = word.strip_prefix(fused).filter(|rest| !rest.is_empty()) {
+            let mut words = vec![fused];
+            words.extend(split_fused_words(rest));
+            return words;
+        }
+    }
+    vec![word]
+}
+
+fn parse_id(main: &ElementRef, link: &str) -> Result<String, ParseError> {
+    let heading = main
+        .select(&Selector::parse("h1").unwrap())
         .next()
-        .unwrap_or_else(|| todo!())
-        .text()
-        .nth(1)
-        .unwrap_or_else(|| todo!())
-        .trim()
-        .to_string()
+        .ok_or_else(|| ParseError::Missing {
+            field: "ID",
+            selector: "h1",
+            link: link.to_owned(),
+        })?;
+    let id = heading.text().nth(1).ok_or_else(|| ParseError::Malformed {
+        field: "ID",
+        selector: "h1",
+        link: link.to_owned(),
+    })?;
+    Ok(id.trim().to_string())
 }
-fn parse_name(main: &ElementRef) -> String {
-    main.select(&Selector::parse("h1").unwrap())
+fn parse_name(main: &ElementRef, link: &str) -> Result<String, ParseError> {
+    let heading = main
+        .select(&Selector::parse("h1").unwrap())
         .next()
-        .unwrap_or_else(|| todo!())
-        .text()
-        .last()
-        .unwrap_or_else(|| todo!())
-        .trim()
-        .to_string()
+        .ok_or_else(|| ParseError::Missing {
+            field: "name",
+            selector: "h1",
+            link: link.to_owned(),
+        })?;
+    let name = heading.text().last().ok_or_else(|| ParseError::Malformed {
+        field: "name",
+        selector: "h1",
+        link: link.to_owned(),
+    })?;
+    Ok(name.trim().to_string())
 }
-fn parse_description(main: &ElementRef) -> String {
+fn parse_description(main: &ElementRef, link: &str) -> Result<String, ParseError> {
     let flatten = regex::Regex::new(r"\s+").unwrap();
     let description = main
         .select(&Selector::parse("div").unwrap())
         //println!("{}", serde_json::to_string_pretty(&courses).unwrap());
         .find(|element| element.value().attr("class") == Some("desc"))
-        .unwrap_or_else(|| todo!())
+        .ok_or_else(|| ParseError::Missing {
+            field: "description",
+            selector: "div.desc",
+            link: link.to_owned(),
+        })?
         .text()
         .collect::<String>()
         //.context("last element not found in description parsing")?
         .replace("\n", " ")
         .replace("\t", " ");
-    flatten.replace_all(&*description, " ").trim().to_string()
+    Ok(flatten.replace_all(&description, " ").trim().to_string())
 }
-fn parse_credits(main: &ElementRef) -> String {
+fn parse_credits(main: &ElementRef, link: &str) -> Result<String, ParseError> {
     let element = main
         .select(&Selector::parse("div").unwrap())
         .find(|element| element.value().attr("class") == Some("sc_credits"));
     match element {
-        None => String::from("0"),
-        Some(element) => element
+        None => Ok(String::from("0")),
+        Some(element) => Ok(element
             .select(&Selector::parse("div").unwrap())
             .find(|element| element.value().attr("class") == Some("credits"))
-            .unwrap_or_else(|| todo!())
+            .ok_or_else(|| ParseError::Missing {
+                field: "credits",
+                selector: "div.sc_credits div.credits",
+                link: link.to_owned(),
+            })?
             .text()
             .collect::<String>()
             .trim()
-            .to_owned(),
+            .to_owned()),
     }
 }
-fn parse_cross_listed(main: &ElementRef) -> Vec<String> {
+fn parse_cross_listed(main: &ElementRef, link: &str) -> Result<Vec<String>, ParseError> {
     // let mut out = vec![];
-    match main
+    let malformed = || ParseError::Malformed {
+        field: "cross listings",
+        selector: "div.sc_credits + h3",
+        link: link.to_owned(),
+    };
+    let cross_listed = match main
         .select(&Selector::parse("div.sc_credits + h3 + a.sc-courselink").unwrap())
         .next()
     {
@@ -288,29 +960,42 @@ fn parse_cross_listed(main: &ElementRef) -> Vec<String> {
             {
                 Some(h3) => h3
                     .next_sibling()
-                    .unwrap()
+                    .ok_or_else(malformed)?
                     .value()
                     .as_text()
-                    .unwrap_or_else(|| todo!())
+                    .ok_or_else(malformed)?
                     .chars()
                     .collect::<String>(),
                 _ => String::from(""),
             }
         }
-    }
-    .split(",")
-    .map(|s| s.trim().to_owned())
-    .collect::<Vec<String>>()
+    };
+    Ok(cross_listed
+        .split(",")
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<String>>())
 }
-fn parse_prerequisites(main: &ElementRef) -> String {
-    main.select(&Selector::parse("div").unwrap())
+fn parse_prerequisites(main: &ElementRef, link: &str) -> Result<String, ParseError> {
+    Ok(main
+        .select(&Selector::parse("div").unwrap())
         .find(|element| element.value().attr("class") == Some("sc_prereqs"))
-        .unwrap_or_else(|| todo!())
+        .ok_or_else(|| ParseError::Missing {
+            field: "prerequisites",
+            selector: "div.sc_prereqs",
+            link: link.to_owned(),
+        })?
         .text()
         .map(|s| s.trim())
         .filter(|s| !s.is_empty() && *s != "Prerequisite")
         .collect::<Vec<&str>>()
-        .join(" ")
+        .join(" "))
+}
+fn parse_prerequisite_tree(main: &ElementRef) -> Option<Prerequisite> {
+    main.select(&Selector::parse("div.sc_prereqs").unwrap())
+        .next()
+        .and_then(|element| Prerequisite::from_element(&element))
 }
 fn parse_offered(main: &ElementRef) -> Vec<String> {
     match main
@@ -320,7 +1005,7 @@ fn parse_offered(main: &ElementRef) -> Vec<String> {
         Some(e) => e
             .text()
             .last()
-            .unwrap_or_else(|| unreachable!())
+            .unwrap_or_default()
             .split("\n")
             .map(|s| s.trim())
             .filter(|s| !s.is_empty())
@@ -337,7 +1022,7 @@ fn parse_distribution(main: &ElementRef) -> Vec<String> {
         Some(value) => value
             .text()
             .last()
-            .unwrap_or_else(|| unreachable!())
+            .unwrap_or_default()
             .split("\n")
             .map(|s| s.trim())
             .filter(|s| !s.is_empty())
@@ -346,3 +1031,110 @@ fn parse_distribution(main: &ElementRef) -> Vec<String> {
         _ => vec![],
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Lists the classes in the fixture JSON API response.
+    fn catalog_listing() -> ClassListing {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/academic-catalog.json");
+        let catalog = serde_json::from_slice::<CatalogNode>(&std::fs::read(path).unwrap()).unwrap();
+        catalog_class_links(STEVENS_HOST, &catalog).unwrap()
+    }
+
+    /// Parses every page in tests/fixtures, naming departments by
+    /// their headings in the fixture JSON API response, and compares the
+    /// result with the golden JSON next to it. Run with UPDATE_GOLDEN=1
+    /// to write the golden files from the current parser instead.
+    #[test]
+    fn fixtures_match_golden_files() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let departments = catalog_listing().departments;
+        let update = std::env::var("UPDATE_GOLDEN").is_ok();
+        let mut pages = std::fs::read_dir(&fixtures)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".page.json"))
+            .collect::<Vec<_>>();
+        pages.sort();
+        assert!(!pages.is_empty(), "no fixtures in {}", fixtures.display());
+
+        let mut failures = vec![];
+        for path in pages {
+            let page = serde_json::from_slice::<ClassPage>(&std::fs::read(&path).unwrap()).unwrap();
+            let parsed = serde_json::to_value(parse_class(page, &departments)).unwrap();
+            let golden = path.to_string_lossy().replace(".page.json", ".json");
+            if update {
+                std::fs::write(
+                    &golden,
+                    serde_json::to_string_pretty(&parsed).unwrap() + "\n",
+                )
+                .unwrap();
+                continue;
+            }
+            let expected = match std::fs::read(&golden) {
+                Ok(bytes) => serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+                Err(_) => {
+                    failures.push(format!("{golden} is missing"));
+                    continue;
+                }
+            };
+            if parsed != expected {
+                failures.push(format!(
+                    "{} no longer parses to {golden}, instead parsing to:\n{}",
+                    path.display(),
+                    serde_json::to_string_pretty(&parsed).unwrap()
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+    }
+
+    /// The JSON API and the courses page should lead to the same links,
+    /// so that switching between them keeps the cached responses.
+    #[test]
+    fn catalog_api_and_courses_page_list_the_same_links() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let page = std::fs::read_to_string(fixtures.join("classes-page.html")).unwrap();
+
+        let api_links = catalog_listing().links;
+        assert_eq!(api_links.len(), 8);
+        assert_eq!(
+            api_links[0],
+            "https://stevens.smartcatalogiq.com/en/2023-2024/academic-catalog/courses/cs-computer-science/100/cs-115/"
+        );
+        assert_eq!(api_links, listed_class_links(STEVENS_HOST, &page));
+
+        let no_courses =
+            serde_json::from_str::<CatalogNode>(r#"{"Path": "/2023-2024/Academic-Catalog"}"#)
+                .unwrap();
+        assert!(catalog_class_links(STEVENS_HOST, &no_courses).is_none());
+    }
+
+    /// Department headings name departments whose links can't be
+    /// turned into their names, while the rest still get a name.
+    #[test]
+    fn departments_are_named_by_their_headings() {
+        let departments = catalog_listing().departments;
+        assert_eq!(departments.get("HLI"), Some("Humanities: Italian"));
+        assert_eq!(
+            departments.get("hss"),
+            Some("Humanities and Social Sciences")
+        );
+        assert_eq!(departments.get("NE"), None);
+
+        let link = format!("https://{STEVENS_HOST}/en/2023-2024/academic-catalog/courses/ne-naval-engineering/400/ne-423/");
+        assert_eq!(parse_department_name(&link).unwrap(), "Naval Engineering");
+        let link = format!("https://{STEVENS_HOST}/en/2023-2024/academic-catalog/courses/hss-humanities-and-social-sciences/300/hss-317/");
+        assert_eq!(
+            parse_department_name(&link).unwrap(),
+            "Humanities and Social Sciences"
+        );
+        let link = format!("https://{STEVENS_HOST}/en/2023-2024/academic-catalog/courses/hmu-humanitiesmusic/100/hmu-101/");
+        assert_eq!(parse_department_name(&link).unwrap(), "Humanities Music");
+        assert!(parse_department_name("https://example.com/ne-423/").is_err());
+    }
+}
@@ -1,30 +1,638 @@
+mod cache;
 mod catalog;
 mod class;
+mod departments;
+mod diff;
+mod file_source;
 mod get_classes;
+mod prerequisite;
 mod traits;
 
 use anyhow::Result;
 use catalog::*;
 use class::*;
+use departments::DepartmentNames;
+use diff::*;
+use file_source::FileSource;
+use get_classes::{SmartCatalog, STEVENS_HOST, STEVENS_INSTITUTION};
+use prerequisite::*;
 use rand::Rng;
 use serenity::async_trait;
-use serenity::builder::CreateEmbed;
+use serenity::builder::{CreateApplicationCommandOption, CreateComponents, CreateEmbed};
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::application_command::CommandDataOption;
+use serenity::model::application::interaction::{Interaction, InteractionResponseType};
 use serenity::model::channel::*;
 use serenity::model::gateway::Ready;
+use serenity::model::id::{RoleId, UserId};
 use serenity::prelude::*;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tantivy::query::QueryParserError;
+use tokio::sync::watch;
 // use thiserror::Error;
 
 const PREFIX: &str = "classy";
 const STEVENS_RED: serenity::utils::Color = serenity::utils::Color::from_rgb(163, 35, 56);
-const CURRENT_YEARS: &str = "2023-2024";
+// There's gotta be a better way to format this
+const HELP: &str = "\
+    __**Commands**__\n\
+    **help**\n\
+      \tGives this message.\n\
+    **query** __class_id__\n\
+      \tGives details about a class.\n\
+      \t*Examples*\n\
+        \t\tclassy query cs 115\n\
+        \t\tclassy query ma125\n\
+    **prereqs** __class_id__\n\
+      \tShows every class needed before a class,\n\
+      \tfollowing prerequisites of prerequisites.\n\
+      \t*Examples*\n\
+        \t\tclassy prereqs cs 385\n\
+    **unlocks** __class_id__\n\
+      \tLists every class that needs a class,\n\
+      \tdirectly or through other classes.\n\
+      \t*Examples*\n\
+        \t\tclassy unlocks cs 284\n\
+    **changes** __year_a__ __year_b__ __department__\n\
+      \tLists classes added, removed or changed\n\
+      \tbetween two catalog years.\n\
+      \t*Defaults*\n\
+        \t\tIf no department is supplied, every\n\
+        \t\tdepartment is compared.\n\
+      \t*Examples*\n\
+        \t\tclassy changes 2023-2024 2024-2025\n\
+        \t\tclassy changes 2023-2024 2024-2025 cs\n\
+    **random** __class_prefix__ __...__\n\
+      \tQueries a random class from the given prefixes.\n\
+      \t*Defaults*\n\
+        \t\tIf no class prefix is supplied, a random\n\
+        \t\tclass from all available classes is picked.\n\
+      \t*Examples*\n\
+        \t\tclassy random\n\
+        \t\tclassy random hli\n\
+        \t\tclassy random cs cpe ee\n\
+    **departments**\n\
+      \tLists all the class departments\n\
+      \tused for class queries.\n\
+    **department** __code__\n\
+      \tLists every class in a department.\n\
+      \t*Options*\n\
+        \t\t--sort number|credits\n\
+        \t\t--level undergrad|grad\n\
+      \t*Examples*\n\
+        \t\tclassy department cs --level grad\n\
+    **aliases**\n\
+      \tLists all the aliases for each command.\n\
+    **calendar**\n\
+      \tReturns the link to the current/upcoming\n\
+      \tyear's academic calendar.\n\
+    **search** __query__\n\
+      \tLists the classes matching a query,\n\
+      \t10 at a time.\n\
+      \t*Fields*\n\
+        \t\tdept, number, level, credits,\n\
+        \t\toffered, distribution, prereqs\n\
+      \t*Examples*\n\
+        \t\tclassy search linear algebra\n\
+        \t\tclassy search dept:cs offered:fall compilers\n\
+        \t\tclassy search level:300..499 credits:>=3\n\
+    __**Options**__\n\
+    **--year** __year__\n\
+      \tUses the catalog for the given academic\n\
+      \tyear instead of the newest one.\n\
+      \t*Examples*\n\
+        \t\tclassy query cs 115 --year 2023-2024\n\
+    ";
+const ALIASES: &str = "\
+    __**Command Aliases**__\n\
+    **help:** h\n\
+    **query:** q\n\
+    **prereqs:** prereq, p\n\
+    **unlocks:** u\n\
+    **changes:** ch\n\
+    **random:** rand, r\n\
+    **departments:** dep, d\n\
+    **department:** dept\n\
+    **aliases:** a\n\
+    **calendar:** c\n\
+    **search:** a\n\
+    ";
+/// Catalog years loaded when CATALOG_YEARS is not set. Every default
+/// year should have an entry in `DEFAULT_CALENDARS`.
+const DEFAULT_YEARS: &str = "2023-2024";
+/// Academic calendar links used when CATALOG_CALENDARS is not set,
+/// in the same `year=link` list form.
+const DEFAULT_CALENDARS: &str = "2023-2024=https://assets.stevens.edu/mviowpldu823/5UlooMY3Cp7TtZctposW1C/d33d938e36645b08425ae48f1844244e/2023-2024_Academic_Calendar03192023__1_.pdf";
+const PREREQUISITE_DEPTH: usize = 8;
+/// Discord shows at most 25 autocomplete choices.
+const AUTOCOMPLETE_CHOICES: usize = 25;
+const SUGGESTIONS: usize = 5;
+const RESULTS_PER_PAGE: usize = 10;
+/// How often catalogs are scraped again, unless set by REFRESH_HOURS.
+const DEFAULT_REFRESH_HOURS: u64 = 24;
+/// The shortest time between edits of a refresh's status message.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// How long an embed description is allowed to grow, leaving room
+/// under Discord's cap of 4096 characters for a closing line.
+const EMBED_DESCRIPTION_BUDGET: usize = 4000;
+/// Discord's limit on the length of a button's custom ID.
+const CUSTOM_ID_LENGTH: usize = 100;
+/// How many arguments too long for a custom ID are remembered at once.
+const STORED_ARGUMENTS: usize = 1000;
 
 struct Handler {
-    catalog: Catalog,
+    /// Shared with the refresh task, which swaps in rescraped catalogs.
+    catalogs: Arc<RwLock<Catalogs>>,
+    /// Where catalogs are loaded from and refreshed from.
+    source: Arc<dyn CatalogSource>,
+    /// Held while any catalog is refreshing, so refreshes never overlap.
+    refreshing: Arc<Mutex<()>>,
+    /// The user allowed to use admin commands, from ADMIN_USER_ID.
+    admin_user: Option<UserId>,
+    /// The role allowed to use admin commands, from ADMIN_ROLE_ID.
+    admin_role: Option<RoleId>,
+    /// Academic calendar links by catalog year, from CATALOG_CALENDARS.
+    calendars: HashMap<String, String>,
+}
+
+/// A response to a command, sent the same way for prefix and slash commands.
+enum Reply {
+    Text(String),
+    Embed(CreateEmbed),
+    /// One page of a longer list, with buttons leading to the pages around it.
+    Page {
+        embed: CreateEmbed,
+        previous: Option<PageId>,
+        next: Option<PageId>,
+    },
+}
+
+impl Reply {
+    /// Returns the buttons for a paginated reply, or no components otherwise.
+    fn components(&self) -> CreateComponents {
+        let mut components = CreateComponents::default();
+        if let Reply::Page { previous, next, .. } = self {
+            components.create_action_row(|row| {
+                for (label, page) in [("Previous", previous), ("Next", next)] {
+                    // Buttons need an ID even while disabled.
+                    let id = page.as_ref().map(PageId::encode);
+                    row.create_button(|button| {
+                        button
+                            .style(ButtonStyle::Secondary)
+                            .label(label)
+                            .disabled(id.is_none())
+                            .custom_id(id.unwrap_or_else(|| label.to_lowercase()))
+                    });
+                }
+                row
+            });
+        }
+        components
+    }
+}
+
+/// Identifies one page of a paginated reply inside a button's custom ID,
+/// as `<command>:<year>:<page>:<arguments>`. Arguments that don't fit are
+/// kept in memory instead, with a `#<key>` in their place.
+struct PageId {
+    command: String,
+    year: String,
+    page: usize,
+    arguments: String,
+}
+
+impl PageId {
+    fn encode(&self) -> String {
+        let id = format!(
+            "{}:{}:{}:{}",
+            self.command, self.year, self.page, self.arguments
+        );
+        // Arguments starting with '#' are stored too, so that they
+        // are never mistaken for a key.
+        if id.len() <= CUSTOM_ID_LENGTH && !self.arguments.starts_with('#') {
+            return id;
+        }
+        let key = stored_arguments().lock().unwrap().insert(&self.arguments);
+        format!("{}:{}:{}:#{key}", self.command, self.year, self.page)
+    }
+    /// Returns the page a custom ID leads to, with Err if its arguments
+    /// were stored and have since been forgotten.
+    fn decode(id: &str) -> Option<Result<PageId, String>> {
+        let mut parts = id.splitn(4, ':');
+        let command = parts.next()?.to_owned();
+        let year = parts.next()?.to_owned();
+        let page = parts.next()?.parse().ok()?;
+        let arguments = parts.next()?;
+        let arguments = match arguments.strip_prefix('#') {
+            Some(key) => match stored_arguments().lock().unwrap().get(key) {
+                Some(arguments) => arguments.to_owned(),
+                None => return Some(Err(String::from(
+                    "These results are too old to page through. Run the command again to see them.",
+                ))),
+            },
+            None => arguments.to_owned(),
+        };
+        Some(Ok(PageId {
+            command,
+            year,
+            page,
+            arguments,
+        }))
+    }
+}
+
+/// Page arguments too long for a custom ID, by the key standing in for
+/// them. Only the newest `STORED_ARGUMENTS` are kept, and they are lost
+/// on restart.
+#[derive(Default)]
+struct StoredArguments {
+    arguments: HashMap<String, String>,
+    keys: VecDeque<String>,
+}
+
+impl StoredArguments {
+    /// Stores arguments and returns their key, a hash of the arguments
+    /// so that every page of one reply shares the same entry.
+    fn insert(&mut self, arguments: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        arguments.hash(&mut hasher);
+        let key = format!("{:016x}", hasher.finish());
+        if self
+            .arguments
+            .insert(key.clone(), arguments.to_owned())
+            .is_none()
+        {
+            self.keys.push_back(key.clone());
+            if self.keys.len() > STORED_ARGUMENTS {
+                let oldest = self.keys.pop_front().unwrap();
+                self.arguments.remove(&oldest);
+            }
+        }
+        key
+    }
+    fn get(&self, key: &str) -> Option<&str> {
+        self.arguments.get(key).map(String::as_str)
+    }
+}
+
+fn stored_arguments() -> &'static std::sync::Mutex<StoredArguments> {
+    static STORED: OnceLock<std::sync::Mutex<StoredArguments>> = OnceLock::new();
+    STORED.get_or_init(Default::default)
 }
 
 impl Handler {
-    fn class_embed(&self, class: &Class) -> CreateEmbed {
+    fn is_admin(&self, msg: &Message) -> bool {
+        let is_user = self.admin_user == Some(msg.author.id);
+        let has_role = match (self.admin_role, &msg.member) {
+            (Some(role), Some(member)) => member.roles.contains(&role),
+            _ => false,
+        };
+        is_user || has_role
+    }
+    /// Runs `classy admin <command>`, replying to the message itself
+    /// since admin commands can take a while.
+    async fn admin_command(&self, context: &Context, msg: &Message, mut arguments: Vec<String>) {
+        let reply = |text: String| async move {
+            if let Err(why) = msg.reply(&context.http, text).await {
+                println!("{:?}", why);
+            }
+        };
+        if !self.is_admin(msg) {
+            return reply(String::from(
+                "Only the bot's admins can use admin commands.",
+            ))
+            .await;
+        }
+        let year = self
+            .take_year(&*self.catalogs.read().await, &mut arguments)
+            .map(|catalog| catalog.year().to_owned());
+        let year = match year {
+            Ok(year) => year,
+            Err(why) => return reply(why).await,
+        };
+        match arguments.first().map(String::as_str) {
+            Some("refresh") => self.admin_refresh(context, msg, &year).await,
+            _ => reply(String::from("Usage: classy admin refresh [--year <year>]")).await,
+        }
+    }
+    /// Scrapes a catalog again and swaps it in, editing a status message
+    /// with the progress along the way.
+    async fn admin_refresh(&self, context: &Context, msg: &Message, year: &str) {
+        let Ok(_refreshing) = self.refreshing.try_lock() else {
+            if let Err(why) = msg
+                .reply(&context.http, "A refresh is already running.")
+                .await
+            {
+                println!("{:?}", why);
+            }
+            return;
+        };
+        let mut status = match msg
+            .reply(&context.http, format!("Refreshing the {year} catalog..."))
+            .await
+        {
+            Ok(status) => status,
+            Err(why) => return println!("{:?}", why),
+        };
+        let (progress, mut receiver) = watch::channel(String::new());
+        let refresh = Catalog::refreshed(&*self.source, year, Some(&progress));
+        tokio::pin!(refresh);
+        // Edits are throttled to stay clear of Discord's rate limits.
+        let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+        let result = loop {
+            tokio::select! {
+                result = &mut refresh => break result,
+                _ = interval.tick() => {
+                    if !receiver.has_changed().unwrap_or(false) {
+                        continue;
+                    }
+                    let line = receiver.borrow_and_update().clone();
+                    if let Err(why) = status.edit(&context.http, |m| m.content(line)).await {
+                        println!("{:?}", why);
+                    }
+                }
+            }
+        };
+        let text = match result {
+            Ok(catalog) => {
+                let classes = catalog.query_by_department("").len();
+                self.catalogs.write().await.replace(catalog);
+                format!("Refreshed the {year} catalog with {classes} classes.")
+            }
+            Err(why) => format!("Failed to refresh the {year} catalog: {why}"),
+        };
+        if let Err(why) = status.edit(&context.http, |m| m.content(text)).await {
+            println!("{:?}", why);
+        }
+    }
+    /// Removes a `--year <year>` option from the arguments and returns
+    /// the catalog for that year, or the newest catalog if none is given.
+    fn take_year<'a>(
+        &self,
+        catalogs: &'a Catalogs,
+        arguments: &mut Vec<String>,
+    ) -> Result<&'a Catalog, String> {
+        let Some(year) = take_option(arguments, "--year", catalogs.newest().year())? else {
+            return Ok(catalogs.newest());
+        };
+        catalogs.year(&year).ok_or_else(|| {
+            format!(
+                "No catalog for year {year}. Available years: {}.",
+                catalogs.years().join(", ")
+            )
+        })
+    }
+    fn not_found_reply(&self, catalog: &Catalog, id: &str) -> Reply {
+        let suggestions = catalog.suggest(id, SUGGESTIONS);
+        if suggestions.is_empty() {
+            return Reply::Text(format!(r#"Class "{id}" not found. Does it exist?"#));
+        }
+        let suggestions = suggestions
+            .iter()
+            .map(|class| format!("**{}** {}", class.id(), class.title()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Reply::Text(format!(
+            "Class \"{id}\" not found. Did you mean:\n{suggestions}"
+        ))
+    }
+    fn query_reply(&self, catalog: &Catalog, id: &str) -> Reply {
+        match catalog.query_by_id(id) {
+            Some(class) => Reply::Embed(self.class_embed(catalog, class)),
+            None => self.not_found_reply(catalog, id),
+        }
+    }
+    fn prereqs_reply(&self, catalog: &Catalog, id: &str) -> Reply {
+        match catalog.query_by_id(id) {
+            Some(class) => match catalog.prerequisite_chain(id, PREREQUISITE_DEPTH) {
+                Some(chain) => Reply::Embed(self.prerequisites_embed(catalog, class, chain)),
+                None => Reply::Text(format!("{} has no prerequisites.", class.id())),
+            },
+            None => self.not_found_reply(catalog, id),
+        }
+    }
+    fn unlocks_reply(&self, catalog: &Catalog, id: &str) -> Reply {
+        match catalog.query_by_id(id).zip(catalog.unlocks(id)) {
+            Some((class, unlocked)) if unlocked.is_empty() => {
+                Reply::Text(format!("No classes list {} as a prerequisite.", class.id()))
+            }
+            Some((class, unlocked)) => Reply::Embed(self.unlocks_embed(catalog, class, unlocked)),
+            None => self.not_found_reply(catalog, id),
+        }
+    }
+    fn changes_reply(&self, catalogs: &Catalogs, arguments: &[String]) -> Reply {
+        let (before, after, department) = match arguments {
+            [before, after] => (before, after, ""),
+            [before, after, department] => (before, after, department.as_str()),
+            _ => {
+                return Reply::Text(String::from(
+                    "Usage: classy changes <year-a> <year-b> [department]",
+                ))
+            }
+        };
+        match catalogs.diff(before, after, department) {
+            Some(diff) if diff.is_empty() => {
+                Reply::Text(format!("No changes between {before} and {after}."))
+            }
+            Some(diff) => Reply::Embed(self.changes_embed(before, after, department, diff)),
+            None => Reply::Text(format!(
+                "Both years need to be loaded. Available years: {}.",
+                catalogs.years().join(", ")
+            )),
+        }
+    }
+    fn random_reply(&self, catalog: &Catalog, mut departments: Vec<String>) -> Reply {
+        if departments.is_empty() {
+            departments.push(String::from(""));
+        }
+        let matches = departments
+            .iter()
+            .fold(Vec::new(), |mut matches, department| {
+                matches.extend(catalog.query_by_department(department));
+                matches.sort_unstable_by_key(|c| c.id());
+                matches.dedup_by_key(|c| c.id());
+                matches
+            });
+        if matches.is_empty() {
+            return Reply::Text(format!(
+                "No classes found for departments [{}]. Do those departments exist?",
+                departments.join(", ")
+            ));
+        }
+        let class = matches
+            .get(rand::thread_rng().gen_range(0..matches.len()))
+            .unwrap();
+        Reply::Embed(self.class_embed(catalog, class))
+    }
+    fn calendar_reply(&self, catalog: &Catalog) -> Reply {
+        Reply::Text(match self.calendars.get(catalog.year()) {
+            Some(link) => format!(
+                "Here is the link for the {} academic calendar: {link}",
+                catalog.year()
+            ),
+            None => format!(
+                "There is no academic calendar link for {} yet.",
+                catalog.year()
+            ),
+        })
+    }
+    fn department_reply(
+        &self,
+        catalog: &Catalog,
+        mut arguments: Vec<String>,
+        page: usize,
+    ) -> Reply {
+        let usage =
+            "Usage: classy department <code> [--sort number|credits] [--level undergrad|grad]";
+        let (sort, level) = match (
+            take_option(&mut arguments, "--sort", "credits"),
+            take_option(&mut arguments, "--level", "grad"),
+        ) {
+            (Ok(sort), Ok(level)) => (sort, level),
+            (Err(why), _) | (_, Err(why)) => return Reply::Text(why),
+        };
+        let sort = match sort.as_deref() {
+            None | Some("number") => ClassSort::Number,
+            Some("credits") => ClassSort::Credits,
+            Some(_) => return Reply::Text(String::from(usage)),
+        };
+        let level = match level.as_deref() {
+            None => None,
+            Some("undergrad" | "undergraduate") => Some(Level::Undergraduate),
+            Some("grad" | "graduate") => Some(Level::Graduate),
+            Some(_) => return Reply::Text(String::from(usage)),
+        };
+        let [code] = arguments.as_slice() else {
+            return Reply::Text(String::from(usage));
+        };
+        let classes = catalog.browse_department(code, sort, level);
+        if classes.is_empty() {
+            return Reply::Text(format!(
+                "No classes found for department {}. Does it exist?",
+                code.to_uppercase()
+            ));
+        }
+        let pages = classes.len().div_ceil(RESULTS_PER_PAGE);
+        let page = page.min(pages - 1);
+        let name = catalog
+            .departments()
+            .into_iter()
+            .find(|(department, _)| department.eq_ignore_ascii_case(code))
+            .map_or_else(
+                || code.to_uppercase(),
+                |(department, name)| format!("{department}: {name}"),
+            );
+        let fields = classes
+            .iter()
+            .skip(page * RESULTS_PER_PAGE)
+            .take(RESULTS_PER_PAGE)
+            .map(|c| {
+                (
+                    format!("{} {}", c.id(), c.title()),
+                    format!(
+                        "*{} credits* -- {} [[^]]({})",
+                        c.credits(),
+                        shorten_description(&c.description()),
+                        c.url()
+                    ),
+                    false,
+                )
+            })
+            .collect::<Vec<_>>();
+        let embed = CreateEmbed::default()
+            .title(format!("{name} (page {} of {pages})", page + 1))
+            .fields(fields)
+            .footer(|f| {
+                f.text(format!(
+                    "Years: {} -- Classes: {}",
+                    catalog.year(),
+                    classes.len()
+                ))
+            })
+            .color(STEVENS_RED)
+            .to_owned();
+        let page_id = |page| PageId {
+            command: String::from("department"),
+            year: catalog.year().to_owned(),
+            page,
+            arguments: format!(
+                "{code} --sort {} {}",
+                match sort {
+                    ClassSort::Number => "number",
+                    ClassSort::Credits => "credits",
+                },
+                match level {
+                    None => "",
+                    Some(Level::Undergraduate) => "--level undergrad",
+                    Some(Level::Graduate) => "--level grad",
+                }
+            ),
+        };
+        Reply::Page {
+            embed,
+            previous: page.checked_sub(1).map(page_id),
+            next: (page + 1 < pages).then(|| page_id(page + 1)),
+        }
+    }
+    fn search_reply(&self, catalog: &Catalog, query: &str, page: usize) -> Reply {
+        let search = |mode| catalog.search(query, page * RESULTS_PER_PAGE, RESULTS_PER_PAGE, mode);
+        // Malformed queries are explained, then searched for as plain text.
+        let (results, problem) = match search(QueryMode::Strict) {
+            Ok(results) => (results, None),
+            Err(SearchError::Query { source }) => match search(QueryMode::Lenient) {
+                Ok(results) => (results, Some(explain_query_error(&source))),
+                Err(_) => {
+                    return Reply::Text(format!(
+                        r#"Couldn't search for "{query}": {}"#,
+                        explain_query_error(&source)
+                    ))
+                }
+            },
+            Err(why) => {
+                println!("Search for {query:?} failed: {why}");
+                return Reply::Text(format!(r#"Something went wrong searching for "{query}"."#));
+            }
+        };
+        if let Some(best) = results.hits.first() {
+            println!(
+                "Search for {query:?} matched {} classes, the best scoring {:.2}.",
+                results.total, best.score
+            );
+        }
+        let pages = results.total.div_ceil(RESULTS_PER_PAGE);
+        let Some(mut embed) = self.class_list_embed(catalog, results.hits) else {
+            return Reply::Text(match problem {
+                Some(problem) => format!(r#"No results for "{query}". Note that {problem}"#),
+                None => format!(r#"No results for "{query}""#),
+            });
+        };
+        embed.title(format!(
+            r#"Results for "{query}" (page {} of {pages})"#,
+            page + 1
+        ));
+        if let Some(problem) = problem {
+            embed.description(format!(
+                "Searched for the words as written, since {problem}"
+            ));
+        }
+        let page_id = |page| PageId {
+            command: String::from("search"),
+            year: catalog.year().to_owned(),
+            page,
+            arguments: query.to_owned(),
+        };
+        Reply::Page {
+            embed,
+            previous: page.checked_sub(1).map(page_id),
+            next: (page + 1 < pages).then(|| page_id(page + 1)),
+        }
+    }
+    fn class_embed(&self, catalog: &Catalog, class: &Class) -> CreateEmbed {
         CreateEmbed::default()
             .title(format!("{} {}", class.id(), class.title()))
             .url(class.url())
@@ -51,40 +659,229 @@ impl Handler {
                 }
                 fields
             })
-            .footer(|f| f.text(format!("Years: {CURRENT_YEARS} -- Classes: {}", self.catalog.query_by_department("").len())))
+            .footer(|f| {
+                f.text(format!(
+                    "Years: {} -- Classes: {}",
+                    catalog.year(),
+                    catalog.query_by_department("").len()
+                ))
+            })
             .color(STEVENS_RED)
             .to_owned()
     }
-    fn class_list_embed(&self, classes: Vec<&Class>) -> Option<CreateEmbed> {
-        if classes.len() > 25 || classes.is_empty() {
+    fn class_list_embed(&self, catalog: &Catalog, hits: Vec<SearchHit>) -> Option<CreateEmbed> {
+        if hits.len() > 25 || hits.is_empty() {
             return None;
         }
-        fn format_description(description: &str) -> String {
-            let description = description.chars().collect::<Vec<char>>();
-            let max_length = 135;
-            if description.len() <= max_length {
-                return description.iter().collect::<String>();
-            }
-            let shortened = description[..max_length-4].iter().rev().collect::<String>();
-            let split = match shortened.split_once(" ") {
-                Some(split) => split.1,
-                None => &*shortened,
+        let fields = hits
+            .iter()
+            .map(|hit| {
+                let c = hit.class;
+                let excerpt = hit
+                    .snippet
+                    .clone()
+                    .unwrap_or_else(|| shorten_description(&c.description()));
+                (
+                    format!("{} {}", c.id(), c.title()),
+                    format!("{excerpt} [[^]]({})", c.url()),
+                    false,
+                )
+            })
+            .collect::<Vec<_>>();
+        Some(
+            CreateEmbed::default()
+                .fields(fields)
+                .footer(|f| {
+                    f.text(format!(
+                        "Years: {} -- Classes: {}",
+                        catalog.year(),
+                        catalog.query_by_department("").len()
+                    ))
+                })
+                .color(STEVENS_RED)
+                .to_owned(),
+        )
+    }
+    fn prerequisites_embed(
+        &self,
+        catalog: &Catalog,
+        class: &Class,
+        chain: Vec<ChainLine>,
+    ) -> CreateEmbed {
+        let mut tree = String::new();
+        for line in chain {
+            let text = match line.kind {
+                ChainKind::All => String::from("all of:"),
+                ChainKind::Any => String::from("one of:"),
+                ChainKind::Course {
+                    id,
+                    corequisite,
+                    state,
+                } => {
+                    let mut text = match catalog.query_by_id(&id) {
+                        Some(class) => format!("{} {}", class.id(), class.title()),
+                        None => format!("{id} (not in catalog)"),
+                    };
+                    if corequisite {
+                        text.push_str(" (may be concurrent)");
+                    }
+                    match state {
+                        ChainState::Repeated => text.push_str(" (see above)"),
+                        ChainState::Cycle => text.push_str(" (cycle)"),
+                        ChainState::Truncated => text.push_str(" ..."),
+                        ChainState::Expanded | ChainState::Leaf => {}
+                    }
+                    text
+                }
+                ChainKind::Standing(text) | ChainKind::Freeform(text) => text,
             };
-            let reassembled = split.chars().rev().collect::<String>();
-            format!("{reassembled} ...")
+            let line = format!("{}{text}\n", "  ".repeat(line.depth));
+            if tree.len() + line.len() > EMBED_DESCRIPTION_BUDGET {
+                tree.push_str("...\n");
+                break;
+            }
+            tree.push_str(&line);
         }
-        let fields = classes.iter().map(|c| (format!("{} {}", c.id(), c.title()), format!("{} [[^]]({})", format_description(&c.description()), c.url()), false)).collect::<Vec<_>>();
-        Some(CreateEmbed::default()
-            .fields(fields)
-            .footer(|f| f.text(format!("Years: {CURRENT_YEARS} -- Classes: {}", self.catalog.query_by_department("").len())))
+        CreateEmbed::default()
+            .title(format!(
+                "Prerequisites for {} {}",
+                class.id(),
+                class.title()
+            ))
+            .url(class.url())
+            .description(format!("```\n{tree}```"))
+            .footer(|f| {
+                f.text(format!(
+                    "Years: {} -- Classes: {}",
+                    catalog.year(),
+                    catalog.query_by_department("").len()
+                ))
+            })
             .color(STEVENS_RED)
-            .to_owned())
+            .to_owned()
     }
-    fn departments_embed(&self) -> CreateEmbed {
+    fn unlocks_embed(
+        &self,
+        catalog: &Catalog,
+        class: &Class,
+        unlocked: Vec<(&Class, usize)>,
+    ) -> CreateEmbed {
+        let mut description = String::new();
+        let mut last_distance = 0;
+        for (unlocked, distance) in unlocked {
+            let mut line = String::new();
+            if distance != last_distance {
+                last_distance = distance;
+                line.push_str(&match distance {
+                    1 => String::from("__**Directly**__\n"),
+                    _ => format!("\n__**{distance} steps away**__\n"),
+                });
+            }
+            line.push_str(&format!(
+                "[{}]({}) {}\n",
+                unlocked.id(),
+                unlocked.url(),
+                unlocked.title()
+            ));
+            if description.len() + line.len() > EMBED_DESCRIPTION_BUDGET {
+                description.push_str("...");
+                break;
+            }
+            description.push_str(&line);
+        }
+        CreateEmbed::default()
+            .title(format!(
+                "Classes unlocked by {} {}",
+                class.id(),
+                class.title()
+            ))
+            .url(class.url())
+            .description(description)
+            .footer(|f| {
+                f.text(format!(
+                    "Years: {} -- Classes: {}",
+                    catalog.year(),
+                    catalog.query_by_department("").len()
+                ))
+            })
+            .color(STEVENS_RED)
+            .to_owned()
+    }
+    fn changes_embed(
+        &self,
+        before: &str,
+        after: &str,
+        department: &str,
+        diff: CatalogDiff,
+    ) -> CreateEmbed {
+        fn shorten(value: &str) -> String {
+            let value = value.chars().collect::<Vec<char>>();
+            match value.len() {
+                0 => String::from("*none*"),
+                1..=80 => value.iter().collect(),
+                _ => format!("{} ...", value[..76].iter().collect::<String>()),
+            }
+        }
+        let mut lines = vec![];
+        if !diff.added.is_empty() {
+            lines.push(format!("__**Added ({})**__", diff.added.len()));
+            lines.extend(
+                diff.added
+                    .iter()
+                    .map(|c| format!("[{}]({}) {}", c.id(), c.url(), c.title())),
+            );
+        }
+        if !diff.removed.is_empty() {
+            lines.push(format!("__**Removed ({})**__", diff.removed.len()));
+            lines.extend(
+                diff.removed
+                    .iter()
+                    .map(|c| format!("{} {}", c.id(), c.title())),
+            );
+        }
+        if !diff.changed.is_empty() {
+            lines.push(format!("__**Changed ({})**__", diff.changed.len()));
+            for changes in diff.changed.iter() {
+                lines.push(format!("[{}]({})", changes.class.id(), changes.class.url()));
+                lines.extend(changes.fields.iter().map(|change| {
+                    format!(
+                        "  {}: {} → {}",
+                        change.field,
+                        shorten(&change.before),
+                        shorten(&change.after)
+                    )
+                }));
+            }
+        }
+        let mut description = String::new();
+        for line in lines {
+            if description.len() + line.len() > EMBED_DESCRIPTION_BUDGET {
+                description.push_str("...");
+                break;
+            }
+            description.push_str(&line);
+            description.push('\n');
+        }
+        let scope = match department.is_empty() {
+            true => String::new(),
+            false => format!(" in {}", department.to_uppercase()),
+        };
+        CreateEmbed::default()
+            .title(format!("Changes from {before} to {after}{scope}"))
+            .description(description)
+            .color(STEVENS_RED)
+            .to_owned()
+    }
+    fn departments_embed(&self, catalog: &Catalog) -> CreateEmbed {
         CreateEmbed::default()
             .title("Class Departments")
             .description(
-                self.catalog.departments().into_iter().map(|t| format!("**{}:** {}\n", t.0, t.1)).collect::<String>().trim()
+                catalog
+                    .departments()
+                    .into_iter()
+                    .map(|t| format!("**{}:** {}\n", t.0, t.1))
+                    .collect::<String>()
+                    .trim(),
             )
             .color(STEVENS_RED)
             .to_owned()
@@ -103,178 +900,511 @@ impl EventHandler for Handler {
             Some(PREFIX) => {}
             _ => return,
         }
-        let statuses = match tokens.next().as_deref() {
-            Some("query" | "q") => {
-                let id = tokens.collect::<String>();
-                let class = self.catalog.query_by_id(&id);
-                let embed = match class {
-                    Some(class) => {
-                        let mut embed = self.class_embed(class);
-                        embed.color(STEVENS_RED);
-                        Some(embed)
-                    }
-                    None => None,
-                };
-                vec![if let Some(embed) = embed {
-                    msg.channel_id
-                        .send_message(&context.http, |m| m.set_embed(embed))
-                        .await
-                } else {
-                    msg.reply(&context.http, format!(r#"Class "{id}" not found. Does it exist?"#))
-                        .await
-                }]
-            }
-            Some("random" | "rand" | "r") => {
-                let mut departments = tokens.collect::<Vec<String>>();
-                if departments.is_empty() {
-                    departments.push(String::from(""));
-                }
-                let matches = departments
-                    .iter()
-                    .fold(Vec::new(), |mut matches, department| {
-                        matches.extend(self.catalog.query_by_department(department));
-                        matches.sort_unstable_by_key(|c| c.id());
-                        matches.dedup_by_key(|c| c.id());
-                        matches
-                    });
-                if matches.is_empty() {
-                    vec![
-                        msg.channel_id
-                            .say(
-                                &context.http,
-                                format!(
-                                    "No classes found for departments [{}]. Do those departments exist?",
-                                    departments.join(", ")
-                                ),
-                            )
-                            .await,
-                    ]
-                } else {
-                    let class = matches
-                        .get(rand::thread_rng().gen_range(0..matches.len()))
-                        .unwrap();
-                    let embed = self.class_embed(class).color(STEVENS_RED).to_owned();
-                    vec![
-                        msg.channel_id
-                            .send_message(&context.http, |m| m.set_embed(embed))
-                            .await,
-                    ]
+        let command = tokens.next();
+        let mut arguments = tokens.collect::<Vec<String>>();
+        if command.as_deref() == Some("admin") {
+            return self.admin_command(&context, &msg, arguments).await;
+        }
+        let catalogs = self.catalogs.read().await;
+        let reply = match self.take_year(&catalogs, &mut arguments) {
+            Ok(catalog) => match command.as_deref() {
+                Some("query" | "q") => self.query_reply(catalog, &arguments.concat()),
+                Some("prereqs" | "prereq" | "p") => {
+                    self.prereqs_reply(catalog, &arguments.concat())
                 }
-            }
-            Some("help" | "h") => {
-                vec![
-                    msg.reply(
-                        &context.http,
-                        // There's gotta be a better way to format this
-                        "\
-                        __**Commands**__\n\
-                        **help**\n\
-                          \tGives this message.\n\
-                        **query** __class_id__\n\
-                          \tGives details about a class.\n\
-                          \t*Examples*\n\
-                            \t\tclassy query cs 115\n\
-                            \t\tclassy query ma125\n\
-                        **random** __class_prefix__ __...__\n\
-                          \tQueries a random class from the given prefixes.\n\
-                          \t*Defaults*\n\
-                            \t\tIf no class prefix is supplied, a random\n\
-                            \t\tclass from all available classes is picked.\n\
-                          \t*Examples*\n\
-                            \t\tclassy random\n\
-                            \t\tclassy random hli\n\
-                            \t\tclassy random cs cpe ee\n\
-                        **departments**\n\
-                          \tLists all the class departments\n\
-                          \tused for class queries.\n\
-                        **aliases**\n\
-                          \tLists all the aliases for each command.\n\
-                        **calendar**\n\
-                          \tReturns the link to the current/upcoming\n\
-                          \tyear's academic calendar.\n\
-                        **search** __query__\n\
-                          \tReturns the top 10 classes for a query.\n\
-                          \t*Examples*\n\
-                            \t\tclassy search linear algebra\n\
-                            \t\tclassy search compilers\n\
-                        "
-                        .trim(),
-                    )
-                    .await,
-                ]
-            }
-            Some("aliases" | "a") => {
-                vec![
-                    msg.reply(
-                        &context.http,
-                        "\
-                        __**Command Aliases**__\n\
-                        **help:** h\n\
-                        **query:** q\n\
-                        **random:** rand, r\n\
-                        **departments:** dep, d\n\
-                        **aliases:** a\n\
-                        **calendar:** c\n\
-                        **search:** a\n\
-                        "
-                        .trim(),
-                    )
-                    .await,
-                ]
-            }
-            Some("departments" | "dep" | "d") => {
+                Some("unlocks" | "u") => self.unlocks_reply(catalog, &arguments.concat()),
+                Some("changes" | "ch") => self.changes_reply(&catalogs, &arguments),
+                Some("random" | "rand" | "r") => self.random_reply(catalog, arguments),
+                Some("help" | "h") => Reply::Text(HELP.trim().to_owned()),
+                Some("aliases" | "a") => Reply::Text(ALIASES.trim().to_owned()),
                 // list all the course prefixes as an embed with fields
-                vec![
-                    msg.channel_id
-                        .send_message(&context.http, |m| m.set_embed(self.departments_embed()))
-                        .await,
-                ]
+                Some("departments" | "dep" | "d") => Reply::Embed(self.departments_embed(catalog)),
+                Some("department" | "dept") => self.department_reply(catalog, arguments, 0),
+                Some("calendar" | "c") => self.calendar_reply(catalog),
+                Some("search" | "s") => self.search_reply(catalog, &arguments.join(" "), 0),
+                _ => return,
+            },
+            Err(why) => Reply::Text(why),
+        };
+        drop(catalogs);
+        let components = reply.components();
+        let status = match reply {
+            Reply::Text(text) => msg.reply(&context.http, text).await,
+            Reply::Embed(embed) | Reply::Page { embed, .. } => {
+                msg.channel_id
+                    .send_message(&context.http, |m| {
+                        m.set_embed(embed).set_components(components)
+                    })
+                    .await
             }
-            Some("calendar" | "c") => {
-                vec![
-                    msg.reply(
-                        &context.http, 
-                        format!("Here is the link for the {CURRENT_YEARS} academic calendar: https://assets.stevens.edu/mviowpldu823/5UlooMY3Cp7TtZctposW1C/d33d938e36645b08425ae48f1844244e/2023-2024_Academic_Calendar03192023__1_.pdf"),
-                    ).await,
-                ]
+        };
+        if let Err(why) = status {
+            println!("{:?}", why);
+        }
+    }
+    async fn interaction_create(&self, context: Context, interaction: Interaction) {
+        let command = match interaction {
+            Interaction::ApplicationCommand(command) => command,
+            Interaction::Autocomplete(autocomplete) => {
+                let options = &autocomplete.data.options;
+                let Some(focused) = options.iter().find(|option| option.focused) else {
+                    return;
+                };
+                let partial = focused
+                    .value
+                    .as_ref()
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default();
+                let catalogs = self.catalogs.read().await;
+                let catalog = option_value(options, "year")
+                    .and_then(|year| catalogs.year(&year))
+                    .unwrap_or_else(|| catalogs.newest());
+                let choices: Vec<_> = match focused.name.as_str() {
+                    "id" => catalog
+                        .complete_id(partial, AUTOCOMPLETE_CHOICES)
+                        .into_iter()
+                        .map(|class| (format!("{} {}", class.id(), class.title()), class.id()))
+                        .collect(),
+                    "department" => catalog
+                        .complete_department(partial, AUTOCOMPLETE_CHOICES)
+                        .into_iter()
+                        .map(|(code, name)| (format!("{code}: {name}"), code))
+                        .collect(),
+                    _ => vec![],
+                };
+                drop(catalogs);
+                let status = autocomplete
+                    .create_autocomplete_response(&context.http, |response| {
+                        for (name, value) in choices {
+                            // Choice names are capped at 100 characters.
+                            response.add_string_choice(
+                                name.chars().take(100).collect::<String>(),
+                                value,
+                            );
+                        }
+                        response
+                    })
+                    .await;
+                if let Err(why) = status {
+                    println!("{:?}", why);
+                }
+                return;
             }
-            Some("search" | "s") => {
-                let query = tokens.collect::<Vec<String>>().join(" ");
-                let matches = self.catalog.search(&query, 10);
-                match self.class_list_embed(matches) {
-                    Some(mut embed) => vec![
-                        msg.channel_id
-                            .send_message(&context.http, |m| m.set_embed((&mut embed).title(format!(r#"Results for "{query}""#)).to_owned()))
-                            .await
-                    ],
-                    None => vec![msg.reply(&context.http, format!(r#"No results for "{query}""#)).await],
+            Interaction::MessageComponent(component) => {
+                let page = match PageId::decode(&component.data.custom_id) {
+                    Some(Ok(page)) => page,
+                    Some(Err(why)) => {
+                        let status = component
+                            .create_interaction_response(&context.http, |response| {
+                                response
+                                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                                    .interaction_response_data(|data| {
+                                        data.content(why).ephemeral(true)
+                                    })
+                            })
+                            .await;
+                        if let Err(why) = status {
+                            println!("{:?}", why);
+                        }
+                        return;
+                    }
+                    None => return,
+                };
+                let catalogs = self.catalogs.read().await;
+                let Some(catalog) = catalogs.year(&page.year) else {
+                    return;
+                };
+                let reply = match page.command.as_str() {
+                    "search" => self.search_reply(catalog, &page.arguments, page.page),
+                    "department" => {
+                        let arguments = page
+                            .arguments
+                            .split_whitespace()
+                            .map(String::from)
+                            .collect();
+                        self.department_reply(catalog, arguments, page.page)
+                    }
+                    _ => return,
+                };
+                drop(catalogs);
+                let status = component
+                    .create_interaction_response(&context.http, |response| {
+                        response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|data| {
+                                data.set_components(reply.components());
+                                match reply {
+                                    Reply::Text(text) => data.content(text).set_embeds(vec![]),
+                                    Reply::Embed(embed) | Reply::Page { embed, .. } => {
+                                        data.set_embed(embed)
+                                    }
+                                }
+                            })
+                    })
+                    .await;
+                if let Err(why) = status {
+                    println!("{:?}", why);
                 }
-            },
+                return;
+            }
             _ => return,
         };
-
-        for status in statuses {
-            match status {
-                Ok(_) => {}
-                Err(why) => println!("{:?}", why),
-            }
+        let option = |name: &str| option_value(&command.data.options, name);
+        let catalogs = self.catalogs.read().await;
+        let catalog = match option("year") {
+            Some(year) => catalogs.year(&year),
+            None => Some(catalogs.newest()),
+        };
+        let reply = match catalog {
+            Some(catalog) => match command.data.name.as_str() {
+                "query" => self.query_reply(catalog, &option("id").unwrap_or_default()),
+                "search" => self.search_reply(catalog, &option("query").unwrap_or_default(), 0),
+                "random" => self.random_reply(catalog, option("department").into_iter().collect()),
+                "departments" => Reply::Embed(self.departments_embed(catalog)),
+                "department" => {
+                    let mut arguments = option("department").into_iter().collect::<Vec<_>>();
+                    for name in ["sort", "level"] {
+                        if let Some(value) = option(name) {
+                            arguments.extend([format!("--{name}"), value]);
+                        }
+                    }
+                    self.department_reply(catalog, arguments, 0)
+                }
+                "calendar" => self.calendar_reply(catalog),
+                _ => return,
+            },
+            None => Reply::Text(format!(
+                "No catalog for that year. Available years: {}.",
+                catalogs.years().join(", ")
+            )),
+        };
+        drop(catalogs);
+        let status = command
+            .create_interaction_response(&context.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|data| {
+                        data.set_components(reply.components());
+                        match reply {
+                            Reply::Text(text) => data.content(text),
+                            Reply::Embed(embed) | Reply::Page { embed, .. } => {
+                                data.add_embed(embed)
+                            }
+                        }
+                    })
+            })
+            .await;
+        if let Err(why) = status {
+            println!("{:?}", why);
         }
     }
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, context: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+        let catalogs = self.catalogs.read().await;
+        let years = catalogs.years();
+        let year_option = |option: &mut CreateApplicationCommandOption| {
+            option
+                .name("year")
+                .description("The academic year of the catalog, defaulting to the newest")
+                .kind(CommandOptionType::String);
+            // Discord allows at most 25 choices per option.
+            for year in years.iter().rev().take(25) {
+                option.add_string_choice(year, year);
+            }
+        };
+        let status = Command::set_global_application_commands(&context.http, |commands| {
+            commands
+                .create_application_command(|command| {
+                    command
+                        .name("query")
+                        .description("Gives details about a class")
+                        .create_option(|option| {
+                            option
+                                .name("id")
+                                .description("The class ID, such as CS 115")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                                .set_autocomplete(true)
+                        })
+                        .create_option(|option| {
+                            year_option(option);
+                            option
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("search")
+                        .description("Lists the classes matching a query")
+                        .create_option(|option| {
+                            option
+                                .name("query")
+                                .description("What to search for, such as linear algebra")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                        .create_option(|option| {
+                            year_option(option);
+                            option
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("random")
+                        .description("Queries a random class")
+                        .create_option(|option| {
+                            option
+                                .name("department")
+                                .description("Only pick from this department, such as HLI")
+                                .kind(CommandOptionType::String)
+                                .set_autocomplete(true)
+                        })
+                        .create_option(|option| {
+                            year_option(option);
+                            option
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("departments")
+                        .description("Lists all the class departments")
+                        .create_option(|option| {
+                            year_option(option);
+                            option
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("department")
+                        .description("Lists every class in a department")
+                        .create_option(|option| {
+                            option
+                                .name("department")
+                                .description("The department code, such as CS")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                                .set_autocomplete(true)
+                        })
+                        .create_option(|option| {
+                            option
+                                .name("sort")
+                                .description("The order to list classes in")
+                                .kind(CommandOptionType::String)
+                                .add_string_choice("By number", "number")
+                                .add_string_choice("By credits", "credits")
+                        })
+                        .create_option(|option| {
+                            option
+                                .name("level")
+                                .description("Only list classes of this level")
+                                .kind(CommandOptionType::String)
+                                .add_string_choice("Undergraduate", "undergrad")
+                                .add_string_choice("Graduate", "grad")
+                        })
+                        .create_option(|option| {
+                            year_option(option);
+                            option
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("calendar")
+                        .description("Returns the link to the academic calendar")
+                        .create_option(|option| {
+                            year_option(option);
+                            option
+                        })
+                })
+        })
+        .await;
+        drop(catalogs);
+        match status {
+            Ok(commands) => println!("Registered {} slash commands.", commands.len()),
+            Err(why) => println!("Failed to register slash commands: {:?}", why),
+        }
+    }
+}
+
+/// Shortens a class description to fit in an embed field, cutting at a word.
+fn shorten_description(description: &str) -> String {
+    let description = description.chars().collect::<Vec<char>>();
+    let max_length = 135;
+    if description.len() <= max_length {
+        return description.iter().collect::<String>();
+    }
+    let shortened = description[..max_length - 4]
+        .iter()
+        .rev()
+        .collect::<String>();
+    let split = match shortened.split_once(" ") {
+        Some(split) => split.1,
+        None => &*shortened,
+    };
+    let reassembled = split.chars().rev().collect::<String>();
+    format!("{reassembled} ...")
+}
+
+/// Describes why a search query could not be read, ending in a period.
+fn explain_query_error(error: &QueryParserError) -> String {
+    match error {
+        QueryParserError::SyntaxError(_) => String::from(
+            "the query could not be read. Check for unbalanced quotes, parentheses or brackets.",
+        ),
+        QueryParserError::FieldDoesNotExist(field) => format!(
+            "there is no field named \"{field}\". Fields are dept, number, level, credits, offered, distribution and prereqs."
+        ),
+        QueryParserError::ExpectedInt(_) | QueryParserError::ExpectedFloat(_) => String::from(
+            "level and credits need numbers, such as level:300..499 or credits:>=3.",
+        ),
+        QueryParserError::AllButQueryForbidden => {
+            String::from("a query can't only exclude words with a leading minus.")
+        }
+        QueryParserError::RangeMustNotHavePhrase => {
+            String::from("ranges need plain values, such as level:[300 TO 499].")
+        }
+        other => format!("the query could not be used ({other})."),
+    }
+}
+
+/// Removes a `<name> <value>` option from the arguments and returns its
+/// value, or an error naming the example if the value is missing.
+fn take_option(
+    arguments: &mut Vec<String>,
+    name: &str,
+    example: &str,
+) -> Result<Option<String>, String> {
+    let Some(position) = arguments.iter().position(|a| a == name) else {
+        return Ok(None);
+    };
+    arguments.remove(position);
+    if position >= arguments.len() {
+        return Err(format!(
+            "Missing value after {name}, such as {name} {example}."
+        ));
+    }
+    Ok(Some(arguments.remove(position)))
+}
+
+/// Parses a comma separated list of `year=link` calendar links.
+fn parse_calendars(list: &str) -> HashMap<String, String> {
+    list.split(',')
+        .filter_map(|entry| {
+            let (year, link) = entry.split_once('=')?;
+            Some((year.trim().to_owned(), link.trim().to_owned()))
+        })
+        .filter(|(year, link)| !year.is_empty() && !link.is_empty())
+        .collect()
+}
+
+/// Returns the value of a string option of a slash command, if it was given.
+fn option_value(options: &[CommandDataOption], name: &str) -> Option<String> {
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_owned())
+}
+
+/// Scrapes every loaded catalog again on a schedule, swapping in each
+/// year once it is rebuilt and keeping the old one if the scrape fails.
+async fn refresh_catalogs(
+    catalogs: Arc<RwLock<Catalogs>>,
+    source: Arc<dyn CatalogSource>,
+    refreshing: Arc<Mutex<()>>,
+    period: Duration,
+) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let years = catalogs
+            .read()
+            .await
+            .years()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        for year in years {
+            let _refreshing = refreshing.lock().await;
+            println!("Refreshing the {year} catalog...");
+            match Catalog::refreshed(&*source, &year, None).await {
+                Ok(catalog) => catalogs.write().await.replace(catalog),
+                Err(why) => println!("Failed to refresh the {year} catalog: {why}"),
+            }
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let catalog = Catalog::new_filled().await?;
+    let years = env::var("CATALOG_YEARS")
+        .unwrap_or_else(|_| String::from(DEFAULT_YEARS))
+        .split(',')
+        .map(|year| year.trim().to_owned())
+        .filter(|year| !year.is_empty())
+        .collect::<Vec<_>>();
+    // Classes come from local files if CATALOG_FILES is set, and otherwise
+    // from the SmartCatalog site at SMARTCATALOG_HOST. Its JSON API is only
+    // used when the institution it is published under is known.
+    // Department names in DEPARTMENTS_FILE replace the ones in the catalog.
+    let department_names = match env::var("DEPARTMENTS_FILE") {
+        Ok(path) => DepartmentNames::load(&path, true)?,
+        Err(_) => DepartmentNames::load("./departments.json", false)?,
+    };
+    let source: Arc<dyn CatalogSource> = match env::var("CATALOG_FILES") {
+        Ok(template) => Arc::new(FileSource::new(&template, department_names)),
+        Err(_) => {
+            let host = env::var("SMARTCATALOG_HOST").unwrap_or_else(|_| String::from(STEVENS_HOST));
+            let institution = env::var("SMARTCATALOG_INSTITUTION")
+                .ok()
+                .or_else(|| (host == STEVENS_HOST).then(|| String::from(STEVENS_INSTITUTION)));
+            Arc::new(SmartCatalog::new(
+                &host,
+                institution.as_deref(),
+                department_names,
+            ))
+        }
+    };
+    let catalogs = Arc::new(RwLock::new(Catalogs::new_filled(&*source, &years).await?));
+    let refreshing = Arc::new(Mutex::new(()));
+    let refresh_hours = env::var("REFRESH_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse().ok())
+        .unwrap_or(DEFAULT_REFRESH_HOURS);
+    if refresh_hours > 0 {
+        let period = Duration::from_secs(refresh_hours * 60 * 60);
+        tokio::spawn(refresh_catalogs(
+            catalogs.clone(),
+            source.clone(),
+            refreshing.clone(),
+            period,
+        ));
+    }
     println!("Starting bot...");
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
-    let intents = GatewayIntents::GUILD_MESSAGES
-        | GatewayIntents::DIRECT_MESSAGES
-        | GatewayIntents::MESSAGE_CONTENT;
+    let mut intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES;
+    // Prefix commands need the privileged message content intent, so bots
+    // without it can turn them off and rely on slash commands alone.
+    if env::var("PREFIX_COMMANDS").map_or(true, |value| value != "false") {
+        intents |= GatewayIntents::MESSAGE_CONTENT;
+    }
     let mut client = Client::builder(&token, intents)
-        .event_handler(Handler { catalog })
+        .event_handler(Handler {
+            catalogs,
+            source,
+            refreshing,
+            admin_user: env::var("ADMIN_USER_ID")
+                .ok()
+                .and_then(|id| id.parse().ok())
+                .map(UserId),
+            admin_role: env::var("ADMIN_ROLE_ID")
+                .ok()
+                .and_then(|id| id.parse().ok())
+                .map(RoleId),
+            calendars: parse_calendars(
+                &env::var("CATALOG_CALENDARS").unwrap_or_else(|_| String::from(DEFAULT_CALENDARS)),
+            ),
+        })
         .await
         .expect("Err creating client");
 
@@ -283,3 +1413,44 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_id(arguments: &str) -> PageId {
+        PageId {
+            command: String::from("search"),
+            year: String::from("2023-2024"),
+            page: 2,
+            arguments: arguments.to_owned(),
+        }
+    }
+
+    #[test]
+    fn long_page_arguments_are_kept_out_of_custom_ids() {
+        let long = "introduction to ".repeat(10);
+        for arguments in ["data structures", "#hashtag", long.as_str()] {
+            let id = page_id(arguments).encode();
+            assert!(id.len() <= CUSTOM_ID_LENGTH, "{id} is too long");
+            let page = PageId::decode(&id).unwrap().unwrap();
+            assert_eq!((page.command.as_str(), page.page), ("search", 2));
+            assert_eq!(page.arguments, arguments);
+        }
+        assert!(PageId::decode("search:2023-2024:2:#forgotten")
+            .unwrap()
+            .is_err());
+        assert!(PageId::decode("not a page").is_none());
+    }
+
+    #[test]
+    fn calendars_are_read_by_year() {
+        let calendars = parse_calendars(
+            "2023-2024=https://example.edu/2023.pdf, 2024-2025 = https://example.edu/2024.pdf?v=2,broken",
+        );
+        assert_eq!(calendars.len(), 2);
+        assert_eq!(calendars["2023-2024"], "https://example.edu/2023.pdf");
+        assert_eq!(calendars["2024-2025"], "https://example.edu/2024.pdf?v=2");
+        assert!(parse_calendars(DEFAULT_CALENDARS).contains_key(DEFAULT_YEARS));
+    }
+}
@@ -0,0 +1,627 @@
+use regex::Regex;
+use scraper::{ElementRef, Node};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+/// A parsed prerequisite requirement.
+///
+/// Built from the `sc_prereqs` div of a class page, where SmartCatalog links
+/// every mentioned course with an `a.sc-courselink` anchor and joins them with
+/// plain "and"/"or" text and parentheses. "and" binds tighter than "or", so
+/// `A and B or C` is read as `(A and B) or C`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Prerequisite {
+    /// Every requirement must be satisfied.
+    All(Vec<Prerequisite>),
+    /// At least one requirement must be satisfied.
+    Any(Vec<Prerequisite>),
+    /// A class that must be taken beforehand, by ID (e.g. "CS 115").
+    Course(String),
+    /// A class that may be taken beforehand or concurrently, by ID.
+    Corequisite(String),
+    /// A class standing requirement such as "Junior standing".
+    Standing(String),
+    /// Anything that could not be understood, kept as the original text.
+    Freeform(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Course(String),
+    Word(String),
+    And,
+    Or,
+    /// Separates the items of a list, which are joined by the
+    /// conjunction that ends the list.
+    Comma,
+    Open,
+    Close,
+}
+
+impl Prerequisite {
+    /// Parses the prerequisite tree out of a `div.sc_prereqs` element.
+    /// Returns None if the element holds no requirements.
+    pub fn from_element(element: &ElementRef) -> Option<Prerequisite> {
+        let mut tokens = Vec::new();
+        tokenize_element(element, &mut tokens);
+        Parser::new(tokens).parse()
+    }
+    /// Parses the prerequisite tree out of flattened prerequisite text,
+    /// recognizing course IDs by their shape instead of by their links.
+    /// Returns None if the text holds no requirements.
+    pub fn from_text(text: &str) -> Option<Prerequisite> {
+        let mut tokens = Vec::new();
+        tokenize_text(text, &mut tokens);
+        Parser::new(tokens).parse()
+    }
+    /// Returns the IDs of every class mentioned in the tree,
+    /// including corequisites, in order of appearance.
+    pub fn courses(&self) -> Vec<String> {
+        let mut courses = Vec::new();
+        self.collect_courses(&mut courses);
+        courses
+    }
+    fn collect_courses(&self, courses: &mut Vec<String>) {
+        match self {
+            Prerequisite::All(children) | Prerequisite::Any(children) => {
+                for child in children {
+                    child.collect_courses(courses);
+                }
+            }
+            Prerequisite::Course(id) | Prerequisite::Corequisite(id) => {
+                if !courses.contains(id) {
+                    courses.push(id.clone());
+                }
+            }
+            Prerequisite::Standing(_) | Prerequisite::Freeform(_) => {}
+        }
+    }
+}
+
+/// Normalizes a course ID to uppercase with a single space
+/// between the department code and the discriminator.
+pub fn normalize_course_id(id: &str) -> String {
+    let id = id.replace('\u{a0}', " ").to_uppercase();
+    let department = id
+        .chars()
+        .take_while(|c| !c.is_ascii_digit())
+        .filter(|c| c.is_ascii_alphabetic())
+        .collect::<String>();
+    let discriminator = id
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>();
+    format!("{department} {discriminator}").trim().to_owned()
+}
+
+fn course_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN
+        .get_or_init(|| Regex::new(r"\b[A-Z]{2,4}[\s\u{a0}]*-?[\s\u{a0}]*\d{3}[A-Z]?\b").unwrap())
+}
+
+fn tokenize_element(element: &ElementRef, tokens: &mut Vec<Token>) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => tokenize_text(text, tokens),
+            Node::Element(value) => {
+                let child = ElementRef::wrap(child).unwrap();
+                if value.name() == "h3" {
+                    continue;
+                }
+                if value.name() == "a" && value.classes().any(|c| c == "sc-courselink") {
+                    tokens.push(Token::Course(normalize_course_id(
+                        &child.text().collect::<String>(),
+                    )));
+                } else {
+                    tokenize_element(&child, tokens);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn tokenize_text(text: &str, tokens: &mut Vec<Token>) {
+    let mut last = 0;
+    for course in course_pattern().find_iter(text) {
+        tokenize_words(&text[last..course.start()], tokens);
+        tokens.push(Token::Course(normalize_course_id(course.as_str())));
+        last = course.end();
+    }
+    tokenize_words(&text[last..], tokens);
+}
+
+fn tokenize_words(text: &str, tokens: &mut Vec<Token>) {
+    let spaced = text
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace(',', " , ")
+        .replace(';', " ; ");
+    let words = spaced.split_whitespace().collect::<Vec<_>>();
+    for (index, word) in words.iter().enumerate() {
+        let lowercase = word.to_lowercase();
+        let next = words.get(index + 1).map(|w| w.to_lowercase());
+        match lowercase.trim_end_matches('.') {
+            "(" => tokens.push(Token::Open),
+            ")" => tokens.push(Token::Close),
+            "and" | "&" | ";" => tokens.push(Token::And),
+            // "a grade of C or better" is a single requirement, not a choice.
+            "or" if matches!(next.as_deref(), Some("better" | "higher" | "above")) => {
+                tokens.push(Token::Word(word.to_string()))
+            }
+            "or" => tokens.push(Token::Or),
+            // A comma right before a conjunction is only punctuation.
+            "," if matches!(next.as_deref(), Some("and" | "or")) => {}
+            "," => tokens.push(Token::Comma),
+            "prerequisite" | "prerequisites" | "prerequisite:" | "prerequisites:" => {}
+            _ => tokens.push(Token::Word(word.to_string())),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens: resolve_lists(tokens),
+            position: 0,
+        }
+    }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+    fn parse(mut self) -> Option<Prerequisite> {
+        let mut parts = Vec::new();
+        while self.peek().is_some() {
+            let start = self.position;
+            parts.extend(self.parse_any());
+            // Skip stray closing parentheses that stop the parse short.
+            if self.position == start {
+                self.position += 1;
+            }
+        }
+        simplify(Prerequisite::All(parts))
+    }
+    fn parse_any(&mut self) -> Option<Prerequisite> {
+        let mut options = vec![];
+        options.extend(self.parse_all());
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            options.extend(self.parse_all());
+        }
+        simplify(Prerequisite::Any(options))
+    }
+    fn parse_all(&mut self) -> Option<Prerequisite> {
+        let mut requirements = vec![];
+        requirements.extend(self.parse_term());
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            requirements.extend(self.parse_term());
+        }
+        simplify(Prerequisite::All(requirements))
+    }
+    fn parse_term(&mut self) -> Option<Prerequisite> {
+        if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            let inner = self.parse_any();
+            if self.peek() == Some(&Token::Close) {
+                self.position += 1;
+            }
+            return inner;
+        }
+        let mut courses = vec![];
+        let mut words = vec![];
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Course(id) => courses.push(id.clone()),
+                Token::Word(word) => words.push(word.clone()),
+                _ => break,
+            }
+            self.position += 1;
+        }
+        atom(courses, words)
+    }
+}
+
+/// Joins the items of every comma separated list by the conjunction that
+/// ends the list, so "A, B, or C" is a single choice while "A, B" and
+/// "A, B and C" require every item. A group starting with "or", as in
+/// "A (or equivalent)", is moved out of its parentheses to become an
+/// alternative to what comes before it.
+fn resolve_lists(mut tokens: Vec<Token>) -> Vec<Token> {
+    for index in 0..tokens.len() {
+        match tokens[index] {
+            Token::Comma => {
+                let mut depth = 0usize;
+                let mut conjunction = Token::And;
+                for token in &tokens[index + 1..] {
+                    match token {
+                        Token::Open => depth += 1,
+                        Token::Close if depth == 0 => break,
+                        Token::Close => depth -= 1,
+                        Token::And | Token::Or if depth == 0 => {
+                            conjunction = token.clone();
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                tokens[index] = conjunction;
+            }
+            Token::Open if tokens.get(index + 1) == Some(&Token::Or) => {
+                tokens.swap(index, index + 1);
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// Turns a run of courses and words between two operators into a requirement.
+/// Words next to a course (e.g. "with a grade of C") are dropped, since the
+/// raw prerequisite text on the class still carries them.
+fn atom(courses: Vec<String>, words: Vec<String>) -> Option<Prerequisite> {
+    let text = words.join(" ");
+    let lowercase = text.to_lowercase();
+    if courses.is_empty() {
+        return match text.is_empty() {
+            true => None,
+            false if lowercase.contains("standing") => Some(Prerequisite::Standing(text)),
+            false => Some(Prerequisite::Freeform(text)),
+        };
+    }
+    let corequisite = ["corequisite", "co-requisite", "concurrent"]
+        .iter()
+        .any(|marker| lowercase.contains(marker));
+    let courses = courses
+        .into_iter()
+        .map(|id| match corequisite {
+            true => Prerequisite::Corequisite(id),
+            false => Prerequisite::Course(id),
+        })
+        .collect();
+    simplify(Prerequisite::All(courses))
+}
+
+/// Flattens nested groups of the same kind and unwraps single-element groups.
+fn simplify(requirement: Prerequisite) -> Option<Prerequisite> {
+    let (children, is_all) = match requirement {
+        Prerequisite::All(children) => (children, true),
+        Prerequisite::Any(children) => (children, false),
+        other => return Some(other),
+    };
+    let mut flattened = Vec::with_capacity(children.len());
+    for child in children {
+        match (child, is_all) {
+            (Prerequisite::All(inner), true) | (Prerequisite::Any(inner), false) => {
+                flattened.extend(inner)
+            }
+            (child, _) => {
+                if !flattened.contains(&child) {
+                    flattened.push(child)
+                }
+            }
+        }
+    }
+    match flattened.len() {
+        0 => None,
+        1 => flattened.pop(),
+        _ if is_all => Some(Prerequisite::All(flattened)),
+        _ => Some(Prerequisite::Any(flattened)),
+    }
+}
+
+/// The prerequisite trees of every class in a catalog, keyed by class ID,
+/// so that chains of requirements can be walked without re-parsing.
+/// Also keeps the reverse edges, from a class to every class that mentions it.
+#[derive(Debug, Default)]
+pub struct PrerequisiteGraph {
+    requirements: HashMap<String, Prerequisite>,
+    dependents: HashMap<String, Vec<String>>,
+}
+
+/// One line of a walked prerequisite chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainLine {
+    /// How many requirements deep the line is, starting at 0.
+    pub depth: usize,
+    pub kind: ChainKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainKind {
+    /// The lines below, one level deeper, must all be satisfied.
+    All,
+    /// At least one of the lines below, one level deeper, must be satisfied.
+    Any,
+    Course {
+        id: String,
+        corequisite: bool,
+        state: ChainState,
+    },
+    Standing(String),
+    Freeform(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainState {
+    /// The class's own prerequisites follow one level deeper.
+    Expanded,
+    /// The class has no prerequisites, or is not in the catalog.
+    Leaf,
+    /// The class was already expanded earlier in the chain.
+    Repeated,
+    /// The class requires itself through the classes above it.
+    Cycle,
+    /// The class has prerequisites but the depth limit was reached.
+    Truncated,
+}
+
+impl PrerequisiteGraph {
+    /// Builds the graph from (class ID, prerequisite tree) pairs.
+    pub fn new(requirements: impl IntoIterator<Item = (String, Option<Prerequisite>)>) -> Self {
+        let requirements = requirements
+            .into_iter()
+            .filter_map(|(id, tree)| Some((normalize_course_id(&id), tree?)))
+            .collect::<HashMap<_, _>>();
+        let mut dependents = HashMap::<String, Vec<String>>::new();
+        for (id, requirement) in requirements.iter() {
+            for course in requirement.courses() {
+                dependents.entry(course).or_default().push(id.clone());
+            }
+        }
+        for ids in dependents.values_mut() {
+            ids.sort_unstable();
+        }
+        Self {
+            requirements,
+            dependents,
+        }
+    }
+    /// Walks every prerequisite of a class transitively and returns the
+    /// chain as indented lines, expanding each class at most once and never
+    /// past `max_depth`. Returns None if the class has no prerequisites.
+    pub fn chain(&self, id: &str, max_depth: usize) -> Option<Vec<ChainLine>> {
+        let id = normalize_course_id(id);
+        let requirement = self.requirements.get(&id)?;
+        let mut walk = ChainWalk {
+            graph: self,
+            max_depth,
+            path: vec![id.clone()],
+            expanded: HashSet::from([id]),
+            lines: vec![],
+        };
+        walk.visit(requirement, 0);
+        Some(walk.lines)
+    }
+    /// Returns every class whose prerequisites mention the given class,
+    /// directly or through other classes, paired with how many steps away
+    /// it is. Direct dependents come first, at a distance of 1.
+    pub fn unlocks(&self, id: &str) -> Vec<(String, usize)> {
+        let id = normalize_course_id(id);
+        let mut seen = HashSet::from([id.clone()]);
+        let mut unlocked = vec![];
+        let mut frontier = vec![id];
+        let mut distance = 1;
+        while !frontier.is_empty() {
+            let mut next = vec![];
+            for id in frontier {
+                for dependent in self.dependents.get(&id).into_iter().flatten() {
+                    if seen.insert(dependent.clone()) {
+                        unlocked.push((dependent.clone(), distance));
+                        next.push(dependent.clone());
+                    }
+                }
+            }
+            frontier = next;
+            distance += 1;
+        }
+        unlocked
+    }
+}
+
+struct ChainWalk<'a> {
+    graph: &'a PrerequisiteGraph,
+    max_depth: usize,
+    path: Vec<String>,
+    expanded: HashSet<String>,
+    lines: Vec<ChainLine>,
+}
+
+impl ChainWalk<'_> {
+    fn visit(&mut self, requirement: &Prerequisite, depth: usize) {
+        let kind = match requirement {
+            Prerequisite::All(children) | Prerequisite::Any(children) => {
+                let kind = match requirement {
+                    Prerequisite::All(_) => ChainKind::All,
+                    _ => ChainKind::Any,
+                };
+                self.lines.push(ChainLine { depth, kind });
+                for child in children {
+                    self.visit(child, depth + 1);
+                }
+                return;
+            }
+            Prerequisite::Course(id) => return self.visit_course(id, false, depth),
+            Prerequisite::Corequisite(id) => return self.visit_course(id, true, depth),
+            Prerequisite::Standing(text) => ChainKind::Standing(text.clone()),
+            Prerequisite::Freeform(text) => ChainKind::Freeform(text.clone()),
+        };
+        self.lines.push(ChainLine { depth, kind });
+    }
+    fn visit_course(&mut self, id: &str, corequisite: bool, depth: usize) {
+        let requirement = self.graph.requirements.get(id);
+        let state = match requirement {
+            None => ChainState::Leaf,
+            Some(_) if self.path.iter().any(|seen| seen == id) => ChainState::Cycle,
+            Some(_) if self.expanded.contains(id) => ChainState::Repeated,
+            Some(_) if depth + 1 > self.max_depth => ChainState::Truncated,
+            Some(_) => ChainState::Expanded,
+        };
+        self.lines.push(ChainLine {
+            depth,
+            kind: ChainKind::Course {
+                id: id.to_owned(),
+                corequisite,
+                state,
+            },
+        });
+        if let (ChainState::Expanded, Some(requirement)) = (state, requirement) {
+            self.expanded.insert(id.to_owned());
+            self.path.push(id.to_owned());
+            self.visit(requirement, depth + 1);
+            self.path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Prerequisite::*;
+
+    fn course(id: &str) -> Prerequisite {
+        Course(id.to_owned())
+    }
+
+    fn line(depth: usize, id: &str, state: ChainState) -> ChainLine {
+        ChainLine {
+            depth,
+            kind: ChainKind::Course {
+                id: id.to_owned(),
+                corequisite: false,
+                state,
+            },
+        }
+    }
+
+    fn graph(requirements: &[(&str, &str)]) -> PrerequisiteGraph {
+        PrerequisiteGraph::new(
+            requirements
+                .iter()
+                .map(|(id, text)| (id.to_string(), Prerequisite::from_text(text))),
+        )
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Prerequisite::from_text("CS 115 and MA 121 or CS 181"),
+            Some(Any(vec![
+                All(vec![course("CS 115"), course("MA 121")]),
+                course("CS 181")
+            ]))
+        );
+        assert_eq!(
+            Prerequisite::from_text("Prerequisite: (CS 115 or CS 181) and (MA 121, MA 122)"),
+            Some(All(vec![
+                Any(vec![course("CS 115"), course("CS 181")]),
+                course("MA 121"),
+                course("MA 122"),
+            ]))
+        );
+        assert_eq!(
+            Prerequisite::from_text("CS 115, CS 181, or CS 135"),
+            Some(Any(vec![
+                course("CS 115"),
+                course("CS 181"),
+                course("CS 135")
+            ]))
+        );
+        assert_eq!(
+            Prerequisite::from_text("CS 115 (or equivalent)"),
+            Some(Any(vec![
+                course("CS 115"),
+                Freeform(String::from("equivalent"))
+            ]))
+        );
+    }
+
+    #[test]
+    fn grades_and_corequisites_stay_with_their_course() {
+        assert_eq!(
+            Prerequisite::from_text("MA 221 with a grade of C or better"),
+            Some(course("MA 221"))
+        );
+        assert_eq!(
+            Prerequisite::from_text("CS 115 and corequisite CS 135"),
+            Some(All(vec![
+                course("CS 115"),
+                Corequisite(String::from("CS 135"))
+            ]))
+        );
+    }
+
+    #[test]
+    fn text_without_courses_is_kept() {
+        assert_eq!(
+            Prerequisite::from_text("Junior standing or permission of the instructor"),
+            Some(Any(vec![
+                Standing(String::from("Junior standing")),
+                Freeform(String::from("permission of the instructor")),
+            ]))
+        );
+        assert_eq!(Prerequisite::from_text("Prerequisites:"), None);
+    }
+
+    #[test]
+    fn chains_stop_at_repeats_cycles_and_the_depth_limit() {
+        let graph = graph(&[
+            ("CS 385", "CS 284 and MA 222"),
+            ("CS 284", "CS 115"),
+            ("MA 222", "CS 284"),
+            ("PEP 111", "PEP 112"),
+            ("PEP 112", "PEP 111"),
+        ]);
+        assert_eq!(
+            graph.chain("cs385", 5).unwrap(),
+            vec![
+                ChainLine {
+                    depth: 0,
+                    kind: ChainKind::All,
+                },
+                line(1, "CS 284", ChainState::Expanded),
+                line(2, "CS 115", ChainState::Leaf),
+                line(1, "MA 222", ChainState::Expanded),
+                line(2, "CS 284", ChainState::Repeated),
+            ]
+        );
+        assert_eq!(
+            graph.chain("CS 385", 1).unwrap()[1],
+            line(1, "CS 284", ChainState::Truncated)
+        );
+        assert_eq!(
+            graph.chain("PEP 111", 5).unwrap(),
+            vec![
+                line(0, "PEP 112", ChainState::Expanded),
+                line(1, "PEP 111", ChainState::Cycle),
+            ]
+        );
+        assert_eq!(graph.chain("CS 115", 5), None);
+    }
+
+    #[test]
+    fn unlocks_lists_dependents_by_distance() {
+        let graph = graph(&[
+            ("CS 385", "CS 284 and MA 222"),
+            ("CS 284", "CS 115"),
+            ("MA 222", "CS 284"),
+        ]);
+        assert_eq!(
+            graph.unlocks("CS 115"),
+            vec![
+                (String::from("CS 284"), 1),
+                (String::from("CS 385"), 2),
+                (String::from("MA 222"), 2),
+            ]
+        );
+        assert!(graph.unlocks("CS 385").is_empty());
+    }
+}
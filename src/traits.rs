@@ -1,3 +1,8 @@
+use crate::prerequisite::Prerequisite;
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::sync::watch;
+
 pub trait Catalog<C: Class> {
     /// Searches the catalog for a course given an ID and returns
     /// a Some reference to it if found, otherwise None.
@@ -28,6 +33,9 @@ pub trait Class {
     fn credits(&self) -> String;
     /// Returns the prerequisites of a class formatted as a single String.
     fn prerequisites(&self) -> String;
+    /// Returns the prerequisites of a class parsed into a requirement tree,
+    /// or None if the class has no prerequisites.
+    fn prerequisite_tree(&self) -> Option<Prerequisite>;
     /// Returns a Vec of the semesters that the class is offered in.
     fn offered(&self) -> Vec<String>;
     /// Returns a Vec of the IDs of any cross listed classes.
@@ -37,3 +45,36 @@ pub trait Class {
     /// Returns a url pointing to an online entry for the class.
     fn url(&self) -> String;
 }
+
+/// Where the classes of a catalog come from, such as a school's
+/// online catalog or a local file.
+#[async_trait]
+pub trait CatalogSource: Send + Sync {
+    /// Returns every class in the given academic year's catalog,
+    /// from the cache where the source keeps one.
+    async fn load(&self, year: &str) -> Result<Vec<crate::class::Class>, SourceError>;
+    /// Returns the latest classes in the given academic year's catalog,
+    /// updating the source's cache with them. Progress lines are also sent to
+    /// `progress` if given.
+    async fn refresh(
+        &self,
+        year: &str,
+        progress: Option<&watch::Sender<String>>,
+    ) -> Result<Vec<crate::class::Class>, SourceError>;
+}
+
+#[derive(Error, Debug)]
+pub enum SourceError {
+    /// Raised by the source itself, such as when its site can't be reached.
+    #[error("could not query the catalog: {source}")]
+    Query {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("{source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+    #[error("could not read {path}: {reason}")]
+    Format { path: String, reason: String },
+}